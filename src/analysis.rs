@@ -0,0 +1,65 @@
+use miette::{Report, Result};
+
+use crate::{
+    checker::Checker,
+    lexer::Lexer,
+    lint::{Level, Lint, Linter},
+    parser::Parser,
+    preprocessor::{LineMap, PreProcessor},
+};
+
+// Everything `roth check` finds, for the cli and the language server
+pub struct Analysis {
+    // the diagnostics are about the expanded source, this says where it came from
+    pub lines: LineMap,
+    pub errors: Vec<Report>,
+    pub warnings: Vec<Report>,
+}
+
+// Errors that stop the preprocessor (i.e. a missing include) are about the
+// program or one of the files it includes, and stop everything else too
+pub fn analyze(mut preprocessor: PreProcessor, lint_levels: &[(Lint, Level)]) -> Result<Analysis> {
+    let (expanded_source, mut errors) = preprocessor.expand_recovering()?;
+
+    let (tokens, lex_errors) = Lexer::new(expanded_source.as_str()).lex_recovering();
+    errors.extend(lex_errors);
+    let mut parser = Parser::new(tokens, expanded_source.clone())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_expansions(preprocessor.expansions().to_vec());
+    let (ops, parse_errors) = parser.parse_recovering();
+    errors.extend(parse_errors.into_iter().map(Report::from));
+
+    let mut checker = Checker::new(expanded_source.clone(), ops.clone())
+        .with_macro_names(preprocessor.macro_names())
+        .with_scopes(preprocessor.scopes().clone());
+    errors.extend(checker.check());
+    // tests run on their own, after the code before them, so they're checked on
+    // their own with the boxes that code makes
+    for test in parser.take_tests() {
+        errors.extend(
+            Checker::new(expanded_source.clone(), test.ops)
+                .with_boxes(ops.range(..test.setup))
+                .with_macro_names(preprocessor.macro_names())
+                .with_scopes(preprocessor.scopes().clone())
+                .check(),
+        );
+    }
+
+    let mut linter =
+        Linter::new(expanded_source.clone(), &ops).with_unused_macros(preprocessor.unused_macros());
+    // what's left on the stack is meaningless if the checker already got confused
+    if errors.is_empty() {
+        linter = linter.with_leftovers(checker.leftovers());
+    }
+    for (lint, level) in lint_levels {
+        linter = linter.with_level(*lint, *level);
+    }
+    let (warnings, denied) = linter.lint();
+    errors.extend(denied);
+
+    Ok(Analysis {
+        lines: preprocessor.lines().clone(),
+        errors,
+        warnings,
+    })
+}
@@ -0,0 +1,159 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::RuntimeError,
+    op::{Op, OpKind},
+};
+
+// Words that reach outside of the program. There aren't any for the environment
+// or for exiting, so those need no capability of their own.
+pub static IO_WORDS: &[&str] = &["print"];
+
+// What a program is allowed to touch, for running code we don't trust. `None`
+// means anything goes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    // directories files can be included from, along with everything under them
+    pub include_roots: Option<Vec<PathBuf>>,
+    // which of `IO_WORDS` can run
+    pub io_words: Option<Vec<String>>,
+    // whether the program can read environment variables and end the process.
+    // No words do either yet, but embedders can already say if they'd be allowed.
+    pub env: Option<bool>,
+    pub exit: Option<bool>,
+}
+
+impl Capabilities {
+    // Nothing is allowed, apart from what already was
+    pub fn sandboxed(self) -> Self {
+        Self {
+            include_roots: Some(self.include_roots.unwrap_or_default()),
+            io_words: Some(self.io_words.unwrap_or_default()),
+            env: Some(self.env.unwrap_or(false)),
+            exit: Some(self.exit.unwrap_or(false)),
+        }
+    }
+
+    // `path` is relative to the current directory, like includes are
+    pub fn can_include(&self, path: &Path) -> bool {
+        let roots = match &self.include_roots {
+            Some(roots) => roots,
+            None => return true,
+        };
+
+        // with `..` and symlinks followed, so they can't be used to get out
+        match path.canonicalize() {
+            Ok(path) => roots
+                .iter()
+                .filter_map(|root| root.canonicalize().ok())
+                .any(|root| path.starts_with(root)),
+            Err(_) => false,
+        }
+    }
+
+    // What to tell someone whose include was turned down
+    pub fn include_help(&self) -> String {
+        let roots: Vec<String> = self
+            .include_roots
+            .iter()
+            .flatten()
+            .map(|root| format!("`{}`", root.display()))
+            .collect();
+        match roots.is_empty() {
+            true => String::from("includes are turned off by `--sandbox`. Let files under a directory be included with `--allow-include=<dir>`"),
+            false => format!(
+                "only files under {} can be included. Add another directory with `--allow-include=<dir>`",
+                roots.join(", ")
+            ),
+        }
+    }
+
+    // Before running `op`
+    pub fn check(&self, source: &str, op: &Op) -> Result<(), RuntimeError> {
+        let word = match op.kind {
+            OpKind::Print => "print",
+            _ => return Ok(()),
+        };
+
+        match &self.io_words {
+            Some(words) if !words.iter().any(|allowed| allowed == word) => Err(
+                RuntimeError::WordNotAllowed(source.to_string(), word.to_string(), op.span.clone()),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        capabilities::Capabilities, lexer::Lexer, parser::Parser, preprocessor::PreProcessor,
+        runtime::Runtime,
+    };
+    use expect_test::expect;
+    use miette::Result;
+
+    fn run(source: &str, capabilities: &Capabilities) -> Result<()> {
+        let source = PreProcessor::new(source)
+            .with_capabilities(capabilities.clone())
+            .expand()?;
+        let tokens = Lexer::new(&source).lex()?;
+        let ops = Parser::new(tokens, source.clone()).parse()?;
+        Runtime::new(source, ops)
+            .with_capabilities(capabilities.clone())
+            .run()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let examples = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let include = |file: &str| format!("include(\"{}\")", examples.join(file).display());
+        let sandboxed = Capabilities::default().sandboxed();
+        let examples_only = Capabilities {
+            include_roots: Some(vec![examples.clone()]),
+            ..Capabilities::default()
+        };
+        let cases = [
+            (include("macro.roth"), Capabilities::default()),
+            (include("macro.roth"), sandboxed.clone()),
+            (include("macro.roth"), examples_only.clone()),
+            (include("../examples/macro.roth"), examples_only.clone()),
+            // `..` doesn't get out of a root
+            (include("../Cargo.toml"), examples_only),
+            (String::from("\"hi\" print"), sandboxed.clone()),
+            (
+                String::from("\"\" print"),
+                Capabilities {
+                    io_words: Some(vec![String::from("print")]),
+                    ..sandboxed
+                },
+            ),
+        ];
+
+        let actual: Vec<String> = cases
+            .iter()
+            .map(|(source, capabilities)| match run(source, capabilities) {
+                Ok(_) => String::from("ok"),
+                Err(e) => format!("{}: {}", e.code().unwrap(), e)
+                    .replace(&examples.display().to_string(), "examples"),
+            })
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "ok",
+                "roth::include_not_allowed: Not allowed to include `examples/macro.roth`",
+                "ok",
+                "ok",
+                "roth::include_not_allowed: Not allowed to include `examples/../Cargo.toml`",
+                "roth::word_not_allowed: Not allowed to run `print`",
+                "ok",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -0,0 +1,759 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+};
+
+use miette::{Report, SourceSpan};
+
+use crate::{
+    error::{CheckError, RuntimeError},
+    lexer::KEYWORDS,
+    modules::Scopes,
+    op::{Op, OpKind},
+    preprocessor::StackEffect,
+    suggest::unknown_box_help,
+    val::ValType,
+};
+
+// What we know about a value without running the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Known(ValType),
+    Type,
+    Unknown,
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Known(val_type) => write!(f, "{}", val_type),
+            Ty::Type => write!(f, "type"),
+            Ty::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AbstractVal {
+    ty: Ty,
+    // where this value was produced
+    span: SourceSpan,
+}
+
+impl AbstractVal {
+    fn new(ty: Ty, span: SourceSpan) -> Self {
+        Self { ty, span }
+    }
+
+    fn is(&self, val_type: ValType) -> bool {
+        self.ty == Ty::Known(val_type)
+    }
+
+    // Unknown values could be anything, so they never cause a type error
+    fn could_be(&self, val_type: ValType) -> bool {
+        self.ty == Ty::Unknown || self.is(val_type)
+    }
+}
+
+// Walks the ops without running them, keeping track of how deep the stack is and
+// what types are on it, so underflows and type errors show up before anything runs
+pub struct Checker {
+    source: String,
+    ops: Vec<Op>,
+    cursor: usize,
+    stack: Vec<AbstractVal>,
+    boxes: HashMap<String, ValType>,
+    errors: Vec<Report>,
+    // when checking the body of a macro against its declared stack effect
+    effect: Option<StackEffect>,
+    macro_effects: HashMap<String, StackEffect>,
+    // only used to suggest names for unknown boxes
+    macro_names: Vec<String>,
+    scopes: Scopes,
+}
+
+impl Checker {
+    pub fn new(source: String, ops: VecDeque<Op>) -> Self {
+        Self {
+            source,
+            ops: ops.into(),
+            cursor: 0,
+            stack: Vec::new(),
+            boxes: HashMap::new(),
+            errors: Vec::new(),
+            effect: None,
+            macro_effects: HashMap::new(),
+            macro_names: Vec::new(),
+            scopes: Scopes::default(),
+        }
+    }
+
+    pub fn with_macro_names(mut self, macro_names: Vec<String>) -> Self {
+        self.macro_names = macro_names;
+        self
+    }
+
+    // Imports and exports, to point someone who left off a module's prefix at it
+    pub fn with_scopes(mut self, scopes: Scopes) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    // Check the ops as the body of a macro: start with the declared inputs on the
+    // stack, and make sure we finish with the declared outputs
+    pub fn with_effect(mut self, effect: StackEffect) -> Self {
+        self.stack = effect
+            .inputs
+            .iter()
+            .map(|val_type| AbstractVal::new(Ty::Known(val_type.clone()), effect.span.clone()))
+            .collect();
+        self.effect = Some(effect);
+        self
+    }
+
+    // Boxes that already exist when the ops run, because `setup` made them (i.e.
+    // the code before a test)
+    pub fn with_boxes<'a>(mut self, setup: impl IntoIterator<Item = &'a Op>) -> Self {
+        for op in setup {
+            if let OpKind::CreateBox { val_type, name } = &op.kind {
+                self.boxes.insert(name.clone(), val_type.clone());
+            }
+        }
+        self
+    }
+
+    // Stack effects of macros that show up by name (unexpanded) in the ops
+    pub fn with_macro_effects(mut self, macro_effects: HashMap<String, StackEffect>) -> Self {
+        self.macro_effects = macro_effects;
+        self
+    }
+
+    pub fn check(&mut self) -> Vec<Report> {
+        while self.cursor < self.ops.len() {
+            let op = self.ops[self.cursor].clone();
+            self.cursor += 1;
+
+            match op.kind {
+                OpKind::If => self.check_if(op),
+                OpKind::Until => self.check_until(op),
+                OpKind::End => self
+                    .errors
+                    .push(RuntimeError::UnexpectedEndToken(self.source.clone(), op.span).into()),
+                _ => self.check_simple(op),
+            }
+        }
+
+        if let (Some(effect), Some(last)) = (&self.effect, self.ops.last()) {
+            let expected: Vec<AbstractVal> = effect
+                .outputs
+                .iter()
+                .map(|val_type| AbstractVal::new(Ty::Known(val_type.clone()), effect.span.clone()))
+                .collect();
+
+            if !same_shape(&expected, &self.stack) {
+                self.errors.push(
+                    CheckError::StackEffectMismatch(
+                        self.source.clone(),
+                        describe(&expected),
+                        describe(&self.stack),
+                        effect.span.clone(),
+                        last.span.clone(),
+                    )
+                    .into(),
+                );
+            }
+        }
+
+        std::mem::take(&mut self.errors)
+    }
+
+    // Boxes could be created in a loop or an `if`, so as long as one gets created
+    // somewhere we don't complain
+    fn creates_box(&self, name: &str) -> bool {
+        self.boxes.contains_key(name)
+            || self.ops.iter().any(
+                |op| matches!(&op.kind, OpKind::CreateBox { name: created, .. } if created == name),
+            )
+    }
+
+    fn unknown_box(&self, name: &str, span: &SourceSpan) -> Report {
+        let candidates: Vec<&str> = KEYWORDS
+            .iter()
+            .map(|(keyword, _)| *keyword)
+            .chain(self.ops.iter().filter_map(|op| match &op.kind {
+                OpKind::CreateBox { name, .. } => Some(name.as_str()),
+                _ => None,
+            }))
+            .chain(self.boxes.keys().map(|name| name.as_str()))
+            .chain(self.macro_names.iter().map(|name| name.as_str()))
+            .collect();
+
+        RuntimeError::UnknownBox(
+            self.source.clone(),
+            unknown_box_help(name, &candidates, &self.scopes),
+            span.clone(),
+        )
+        .into()
+    }
+
+    // Where the values still on the stack after `check` came from
+    pub fn leftovers(&self) -> Vec<SourceSpan> {
+        self.stack.iter().map(|val| val.span.clone()).collect()
+    }
+
+    // Check ops until the corresponding `end`, returning its span
+    fn check_block(&mut self, opener: &Op) -> Option<SourceSpan> {
+        while self.cursor < self.ops.len() {
+            let op = self.ops[self.cursor].clone();
+            self.cursor += 1;
+
+            match op.kind {
+                OpKind::If => self.check_if(op),
+                OpKind::Until => self.check_until(op),
+                OpKind::End => return Some(op.span),
+                _ => self.check_simple(op),
+            }
+        }
+
+        self.errors
+            .push(CheckError::UnclosedBlock(self.source.clone(), opener.span.clone()).into());
+        None
+    }
+
+    fn check_if(&mut self, op: Op) {
+        let condition = self.pop(&op, 1).remove(0);
+        if !condition.could_be(ValType::Bool) {
+            self.errors
+                .push(RuntimeError::IfsExpectBooleans(self.source.clone(), op.span.clone()).into());
+        }
+
+        // the body might not run, so it has to leave the stack exactly like it found it
+        let before = self.stack.clone();
+        if let Some(end_span) = self.check_block(&op) {
+            if !same_shape(&before, &self.stack) {
+                self.errors.push(
+                    CheckError::UnbalancedIf(
+                        self.source.clone(),
+                        describe(&before),
+                        describe(&self.stack),
+                        op.span,
+                        end_span,
+                    )
+                    .into(),
+                );
+
+                // carry on with whichever path has more on the stack, to avoid
+                // reporting a bunch of underflows that are really this error
+                if before.len() > self.stack.len() {
+                    self.stack = before;
+                }
+            } else {
+                self.stack = join(&before, &self.stack);
+            }
+        }
+    }
+
+    fn check_until(&mut self, op: Op) {
+        let condition = self.pop(&op, 1).remove(0);
+        if !condition.could_be(ValType::Bool) {
+            self.errors.push(
+                RuntimeError::UntilsExpectBooleans(self.source.clone(), op.span.clone()).into(),
+            );
+        }
+
+        // after the body runs we jump back to the `until`, which pops a bool again
+        let before = self.stack.clone();
+        if let Some(end_span) = self.check_block(&op) {
+            let balanced = match self.stack.split_last() {
+                Some((top, rest)) => top.could_be(ValType::Bool) && same_shape(&before, rest),
+                None => false,
+            };
+
+            if !balanced {
+                let mut expected = before.clone();
+                expected.push(AbstractVal::new(Ty::Known(ValType::Bool), op.span.clone()));
+                self.errors.push(
+                    CheckError::UnbalancedUntil(
+                        self.source.clone(),
+                        describe(&expected),
+                        describe(&self.stack),
+                        op.span,
+                        end_span,
+                    )
+                    .into(),
+                );
+            }
+
+            self.stack = before;
+        }
+    }
+
+    fn check_simple(&mut self, op: Op) {
+        let span = op.span.clone();
+        match &op.kind {
+            OpKind::PushInt { .. } => self.push(Ty::Known(ValType::Int), span),
+            OpKind::PushStr { .. } => self.push(Ty::Known(ValType::Str), span),
+            OpKind::PushBool { .. } => self.push(Ty::Known(ValType::Bool), span),
+            OpKind::PushTypeInt
+            | OpKind::PushTypeStr
+            | OpKind::PushTypeBool
+            | OpKind::PushTypeBoxedInt
+            | OpKind::PushTypeBoxedStr
+            | OpKind::PushTypeBoxedBool => self.push(Ty::Type, span),
+            OpKind::CreateBox { val_type, name } => {
+                self.boxes.insert(name.clone(), val_type.clone());
+            }
+            OpKind::PushBox { name } if self.macro_effects.contains_key(name) => {
+                let effect = self.macro_effects[name].clone();
+                let args = self.pop(&op, effect.inputs.len());
+                for (arg, expected) in args.iter().zip(&effect.inputs) {
+                    if !arg.could_be(expected.clone()) {
+                        self.errors.push(
+                            CheckError::ArgumentTypeMismatch(
+                                self.source.clone(),
+                                name.clone(),
+                                expected.clone(),
+                                arg.span.clone(),
+                                span.clone(),
+                            )
+                            .into(),
+                        );
+                    }
+                }
+                for output in effect.outputs {
+                    self.push(Ty::Known(output), span.clone());
+                }
+            }
+            OpKind::PushBox { name } => {
+                if self.effect.is_none() && !self.creates_box(name) {
+                    self.errors.push(self.unknown_box(name, &span));
+                }
+
+                let ty = match self.boxes.get(name) {
+                    Some(ValType::Int) => Ty::Known(ValType::BoxedInt),
+                    Some(ValType::Str) => Ty::Known(ValType::BoxedStr),
+                    Some(ValType::Bool) => Ty::Known(ValType::BoxedBool),
+                    _ => Ty::Unknown,
+                };
+                self.push(ty, span);
+            }
+            OpKind::Pack => {
+                let mut vals = self.pop(&op, 2);
+                let (val, b) = (vals.remove(0), vals.remove(0));
+                let inner = match b.ty {
+                    Ty::Known(ValType::BoxedInt) => Some(ValType::Int),
+                    Ty::Known(ValType::BoxedStr) => Some(ValType::Str),
+                    Ty::Known(ValType::BoxedBool) => Some(ValType::Bool),
+                    Ty::Unknown => None,
+                    _ => {
+                        self.errors.push(
+                            RuntimeError::CanOnlyPackBoxes(self.source.clone(), b.span).into(),
+                        );
+                        None
+                    }
+                };
+                if let Some(inner) = inner {
+                    if !val.could_be(inner.clone()) {
+                        self.errors.push(
+                            RuntimeError::IncompatibleBox(self.source.clone(), inner, val.span)
+                                .into(),
+                        );
+                    }
+                }
+            }
+            OpKind::Unpack => {
+                let b = self.pop(&op, 1).remove(0);
+                let ty = match b.ty {
+                    Ty::Known(ValType::BoxedInt) => Ty::Known(ValType::Int),
+                    Ty::Known(ValType::BoxedStr) => Ty::Known(ValType::Str),
+                    Ty::Known(ValType::BoxedBool) => Ty::Known(ValType::Bool),
+                    Ty::Unknown => Ty::Unknown,
+                    _ => {
+                        self.errors.push(
+                            RuntimeError::CanOnlyUnpackBoxes(self.source.clone(), b.span).into(),
+                        );
+                        Ty::Unknown
+                    }
+                };
+                self.push(ty, span);
+            }
+            OpKind::Add => {
+                let (x, y) = self.pop_pair(&op);
+                let ty = match (&x.ty, &y.ty) {
+                    (Ty::Known(ValType::Int), Ty::Known(ValType::Int)) => Ty::Known(ValType::Int),
+                    (Ty::Known(ValType::Str), Ty::Known(ValType::Str | ValType::Int))
+                    | (Ty::Known(ValType::Int), Ty::Known(ValType::Str)) => Ty::Known(ValType::Str),
+                    (Ty::Unknown, Ty::Known(ValType::Int | ValType::Str))
+                    | (Ty::Known(ValType::Int | ValType::Str), Ty::Unknown)
+                    | (Ty::Unknown, Ty::Unknown) => Ty::Unknown,
+                    _ => {
+                        self.errors.push(
+                            RuntimeError::InvalidAdd(
+                                self.source.clone(),
+                                x.span.clone(),
+                                y.span.clone(),
+                            )
+                            .into(),
+                        );
+                        Ty::Unknown
+                    }
+                };
+                self.push(ty, merge(&x.span, &span));
+            }
+            OpKind::Sub | OpKind::Mul | OpKind::Div => {
+                let (x, y) = self.pop_pair(&op);
+                if !x.could_be(ValType::Int) || !y.could_be(ValType::Int) {
+                    let err = match op.kind {
+                        OpKind::Sub => RuntimeError::InvalidSub,
+                        OpKind::Mul => RuntimeError::InvalidMul,
+                        _ => RuntimeError::InvalidDiv,
+                    };
+                    self.errors
+                        .push(err(self.source.clone(), x.span.clone(), y.span.clone()).into());
+                }
+                self.push(Ty::Known(ValType::Int), merge(&x.span, &span));
+            }
+            OpKind::Or | OpKind::And => {
+                let (x, y) = self.pop_pair(&op);
+                if !x.could_be(ValType::Bool) || !y.could_be(ValType::Bool) {
+                    let err = match op.kind {
+                        OpKind::Or => RuntimeError::InvalidOr,
+                        _ => RuntimeError::InvalidAnd,
+                    };
+                    self.errors
+                        .push(err(self.source.clone(), x.span.clone(), y.span.clone()).into());
+                }
+                self.push(Ty::Known(ValType::Bool), merge(&x.span, &span));
+            }
+            OpKind::Not => {
+                let x = self.pop(&op, 1).remove(0);
+                if !x.could_be(ValType::Bool) {
+                    self.errors
+                        .push(RuntimeError::InvalidNot(self.source.clone(), x.span.clone()).into());
+                }
+                self.push(Ty::Known(ValType::Bool), merge(&x.span, &span));
+            }
+            OpKind::Eq => {
+                let (x, y) = self.pop_pair(&op);
+                let comparable = match (&x.ty, &y.ty) {
+                    (Ty::Unknown, _) | (_, Ty::Unknown) => true,
+                    (Ty::Type, Ty::Type) => true,
+                    (Ty::Known(a), Ty::Known(b)) => {
+                        a == b && matches!(a, ValType::Int | ValType::Str | ValType::Bool)
+                    }
+                    _ => false,
+                };
+                if !comparable {
+                    self.errors.push(
+                        RuntimeError::InvalidEq(
+                            self.source.clone(),
+                            x.span.clone(),
+                            y.span.clone(),
+                        )
+                        .into(),
+                    );
+                }
+                self.push(Ty::Known(ValType::Bool), merge(&x.span, &span));
+            }
+            OpKind::LessThan | OpKind::GreaterThan | OpKind::LessThanEq | OpKind::GreaterThanEq => {
+                let (x, y) = self.pop_pair(&op);
+                let comparable = match (&x.ty, &y.ty) {
+                    (Ty::Unknown, Ty::Known(ValType::Int | ValType::Str))
+                    | (Ty::Known(ValType::Int | ValType::Str), Ty::Unknown)
+                    | (Ty::Unknown, Ty::Unknown) => true,
+                    (Ty::Known(a), Ty::Known(b)) => {
+                        a == b && matches!(a, ValType::Int | ValType::Str)
+                    }
+                    _ => false,
+                };
+                if !comparable {
+                    let err = match op.kind {
+                        OpKind::LessThan => RuntimeError::InvalidLessThan,
+                        OpKind::GreaterThan => RuntimeError::InvalidGreaterThan,
+                        OpKind::LessThanEq => RuntimeError::InvalidLessThanEq,
+                        _ => RuntimeError::InvalidGreaterThanEq,
+                    };
+                    self.errors
+                        .push(err(self.source.clone(), x.span.clone(), y.span.clone()).into());
+                }
+                self.push(Ty::Known(ValType::Bool), merge(&x.span, &span));
+            }
+            OpKind::Print | OpKind::Drop => {
+                self.pop(&op, 1);
+            }
+            OpKind::Dup => {
+                let a = self.pop(&op, 1).remove(0);
+                self.stack.push(a.clone());
+                self.stack.push(a);
+            }
+            OpKind::Swap => {
+                let (a, b) = self.pop_pair(&op);
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            OpKind::Over => {
+                let (a, b) = self.pop_pair(&op);
+                self.stack.push(a.clone());
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            OpKind::Rot => {
+                let mut vals = self.pop(&op, 3);
+                let (a, b, c) = (vals.remove(0), vals.remove(0), vals.remove(0));
+                self.stack.push(b);
+                self.stack.push(c);
+                self.stack.push(a);
+            }
+            OpKind::GetType => {
+                let x = self.pop(&op, 1).remove(0);
+                self.push(Ty::Type, merge(&x.span, &span));
+            }
+            OpKind::Assert => {
+                let x = self.pop(&op, 1).remove(0);
+                if !x.could_be(ValType::Bool) {
+                    self.errors
+                        .push(RuntimeError::InvalidAssert(self.source.clone(), span).into());
+                }
+            }
+            OpKind::Breakpoint => {}
+            // any two values can be compared
+            OpKind::AssertEq | OpKind::AssertNe => {
+                self.pop(&op, 2);
+            }
+            OpKind::AssertType => {
+                let (_, ty) = self.pop_pair(&op);
+                if ty.ty != Ty::Type && ty.ty != Ty::Unknown {
+                    self.errors.push(
+                        RuntimeError::InvalidAssertType(self.source.clone(), ty.span, span).into(),
+                    );
+                }
+            }
+            OpKind::AssertStackDepth => {
+                let x = self.pop(&op, 1).remove(0);
+                if !x.could_be(ValType::Int) {
+                    self.errors.push(
+                        RuntimeError::InvalidAssertStackDepth(self.source.clone(), x.span, span)
+                            .into(),
+                    );
+                }
+            }
+            OpKind::If | OpKind::End | OpKind::Until => {
+                unreachable!("blocks should have already been processed")
+            }
+        }
+    }
+
+    fn push(&mut self, ty: Ty, span: SourceSpan) {
+        self.stack.push(AbstractVal::new(ty, span));
+    }
+
+    // Pop `n` values (bottom-most first). If there aren't enough, report it and
+    // make up unknown values so checking can carry on.
+    fn pop(&mut self, op: &Op, n: usize) -> Vec<AbstractVal> {
+        let available = self.stack.len();
+        if available < n {
+            let error = match &self.effect {
+                Some(effect) => CheckError::StackEffectUnderflow(
+                    self.source.clone(),
+                    self.op_text(op),
+                    n,
+                    available,
+                    effect.span.clone(),
+                    op.span.clone(),
+                ),
+                None => CheckError::StackUnderflow(
+                    self.source.clone(),
+                    self.op_text(op),
+                    n,
+                    available,
+                    op.span.clone(),
+                ),
+            };
+            self.errors.push(error.into());
+
+            let mut vals = vec![AbstractVal::new(Ty::Unknown, op.span.clone()); n - available];
+            vals.append(&mut self.stack);
+            return vals;
+        }
+
+        self.stack.split_off(available - n)
+    }
+
+    fn pop_pair(&mut self, op: &Op) -> (AbstractVal, AbstractVal) {
+        let mut vals = self.pop(op, 2);
+        let y = vals.remove(1);
+        let x = vals.remove(0);
+        (x, y)
+    }
+
+    fn op_text(&self, op: &Op) -> String {
+        self.source
+            .get(op.span.offset()..op.span.offset() + op.span.len())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+fn same_shape(a: &[AbstractVal], b: &[AbstractVal]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(x, y)| x.ty == y.ty || x.ty == Ty::Unknown || y.ty == Ty::Unknown)
+}
+
+// Where two paths meet, keep what they agree on
+fn join(a: &[AbstractVal], b: &[AbstractVal]) -> Vec<AbstractVal> {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| {
+            if x.ty == y.ty {
+                y.clone()
+            } else {
+                AbstractVal::new(Ty::Unknown, y.span.clone())
+            }
+        })
+        .collect()
+}
+
+fn describe(stack: &[AbstractVal]) -> String {
+    let types: Vec<String> = stack.iter().map(|v| v.ty.to_string()).collect();
+    format!("[{}]", types.join(" "))
+}
+
+fn merge(start: &SourceSpan, end: &SourceSpan) -> SourceSpan {
+    let off = start.offset();
+    let len = end.offset() + end.len();
+
+    (off, len.saturating_sub(off)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{checker::Checker, lexer::Lexer, parser::Parser, preprocessor::PreProcessor};
+    use expect_test::expect;
+
+    fn test(source: &str) -> Vec<String> {
+        let tokens = Lexer::new(source).lex().unwrap();
+        let ops = Parser::new(tokens, source.to_string()).parse().unwrap();
+
+        Checker::new(source.to_string(), ops)
+            .check()
+            .iter()
+            .map(|e| e.code().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_well_formed_program() {
+        let actual = test(
+            "box type::int sum
+0 sum pack
+false until
+  sum unpack 1 + sum pack
+  sum unpack 5 eq
+end
+sum unpack 5 eq if \"done\" print end",
+        );
+
+        let expected = expect![[r#"
+            []
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_underflow_and_type_mismatch() {
+        let actual = test("1 + \"a\" 2 - not");
+
+        let expected = expect![[r#"
+            [
+                "roth::stack_underflow",
+                "roth::invalid_sub",
+                "roth::invalid_not",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_unbalanced_blocks() {
+        let actual = test("true if 1 end false until 1 end 1 if");
+
+        let expected = expect![[r#"
+            [
+                "roth::unbalanced_if",
+                "roth::unbalanced_until",
+                "roth::ifs_expect_booleans",
+                "roth::unclosed_block",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_stack_effects() {
+        let actual: Vec<String> = [
+            "macro sq ( int -- int ) dup * end macro sq2 ( int -- int ) sq sq end 3 sq2",
+            "macro sq ( int -- int int ) dup * end",
+            "macro twice ( int -- int ) + end",
+            "macro sq ( int -- int ) dup * end \"a\" sq",
+        ]
+        .iter()
+        .map(|source| match PreProcessor::new(source).expand() {
+            Ok(expanded) => test(&expanded).join(" "),
+            Err(e) => e.code().unwrap().to_string(),
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "",
+                "roth::stack_effect_mismatch",
+                "roth::stack_effect_underflow",
+                "roth::invalid_mul",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_tests_see_setup_boxes() {
+        let source = "box type::int x
+1 x pack
+test \"reads\" x unpack 1 assert-eq end
+test \"wrong type\" \"a\" x pack end
+test \"later box\" y unpack end
+box type::int y";
+        let tokens = Lexer::new(source).lex().unwrap();
+        let mut parser = Parser::new(tokens, source.to_string());
+        let ops = parser.parse().unwrap();
+
+        let actual: Vec<String> = parser
+            .take_tests()
+            .into_iter()
+            .map(|test| {
+                let errors = Checker::new(source.to_string(), test.ops)
+                    .with_boxes(ops.range(..test.setup))
+                    .check();
+                let codes: Vec<String> = errors
+                    .iter()
+                    .map(|e| e.code().unwrap().to_string())
+                    .collect();
+                format!("{}: {}", test.name, codes.join(" "))
+            })
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "reads: ",
+                "wrong type: roth::incompatible_box",
+                "later box: roth::unknown_box",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
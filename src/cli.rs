@@ -0,0 +1,428 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use miette::{Report, Result};
+
+use crate::{
+    capabilities::{Capabilities, IO_WORDS},
+    coverage::CoverageOutput,
+    error::{CliError, ParseError},
+    json,
+    limits::Limits,
+    lint::{Level, Lint, LINTS},
+    preprocessor::PreProcessor,
+    profile::ProfileOutput,
+    trace::TraceFormat,
+};
+
+// Flags that can go anywhere on the command line
+pub struct Options {
+    pub json_messages: bool,
+    pub lint_levels: Vec<(Lint, Level)>,
+    // check macros against their stack effects while running
+    pub debug: bool,
+    // `roth fmt --check`: only say whether the program is formatted
+    pub check_formatting: bool,
+    // `roth test --filter=<text>`: only run tests with `<text>` in their name
+    pub test_filter: Option<String>,
+    // `--trace` or `--trace=json`
+    pub trace: Option<TraceFormat>,
+    // `--profile` or `--profile=<file>`
+    pub profile: Option<ProfileOutput>,
+    // `--coverage` or `--coverage=<file>`
+    pub coverage: Option<CoverageOutput>,
+    // `--max-steps=`, `--max-time=`, `--max-stack=` and `--max-memory=`
+    pub limits: Limits,
+    // `--sandbox`, `--allow-include=<dir>`, `--allow-io=<word>`, `--allow-env`
+    // and `--allow-exit`
+    pub capabilities: Capabilities,
+    // `-I <dir>`: where else to look for includes
+    pub include_dirs: Vec<PathBuf>,
+}
+
+impl Options {
+    // Takes our flags out of `args`, leaving everything else where it was
+    pub fn parse(args: &mut Vec<String>) -> Result<Self> {
+        let mut options = Options {
+            json_messages: false,
+            lint_levels: Vec::new(),
+            debug: false,
+            check_formatting: false,
+            test_filter: None,
+            trace: None,
+            profile: None,
+            coverage: None,
+            limits: Limits::default(),
+            capabilities: Capabilities::default(),
+            include_dirs: Vec::new(),
+        };
+        let mut sandbox = false;
+
+        let mut rest = Vec::new();
+        let mut drained = args.drain(..);
+        while let Some(arg) = drained.next() {
+            // the code after `-e` could look like anything, flags included
+            if arg == "-e" {
+                rest.push(arg);
+                rest.extend(drained.next());
+                continue;
+            }
+            if let Some(dir) = arg.strip_prefix("-I") {
+                let dir = match dir {
+                    "" => drained.next().ok_or(CliError::MissingIncludeDir)?,
+                    dir => dir.to_string(),
+                };
+                options.include_dirs.push(PathBuf::from(dir));
+                continue;
+            }
+
+            let level = if let Some(lint) = arg.strip_prefix("--allow=") {
+                Some((Level::Allow, lint.to_string()))
+            } else if let Some(lint) = arg.strip_prefix("--warn=") {
+                Some((Level::Warn, lint.to_string()))
+            } else {
+                arg.strip_prefix("--deny=")
+                    .map(|lint| (Level::Deny, lint.to_string()))
+            };
+
+            match level {
+                // `warnings` means every lint
+                Some((level, name)) if name == "warnings" => {
+                    options
+                        .lint_levels
+                        .extend(LINTS.iter().map(|(_, lint)| (*lint, level)));
+                }
+                Some((level, name)) => match LINTS.iter().find(|(lint, _)| *lint == name) {
+                    Some((_, lint)) => options.lint_levels.push((*lint, level)),
+                    None => return Err(CliError::UnknownLint(name).into()),
+                },
+                None if arg == "--message-format=json" => options.json_messages = true,
+                None if arg == "--debug" => options.debug = true,
+                None if arg == "--trace" || arg == "--trace=text" => {
+                    options.trace = Some(TraceFormat::Text)
+                }
+                None if arg == "--trace=json" => options.trace = Some(TraceFormat::Json),
+                None if arg == "--profile" => options.profile = Some(ProfileOutput::Report),
+                None if arg.starts_with("--profile=") => {
+                    let file = PathBuf::from(&arg["--profile=".len()..]);
+                    options.profile = Some(ProfileOutput::Folded(file));
+                }
+                None if arg == "--coverage" => options.coverage = Some(CoverageOutput::Annotated),
+                None if arg.starts_with("--coverage=") => {
+                    let file = PathBuf::from(&arg["--coverage=".len()..]);
+                    options.coverage = Some(CoverageOutput::Lcov(file));
+                }
+                None if LIMITS.contains(&arg.split('=').next().unwrap_or_default()) => {
+                    parse_limit(&arg, &mut options.limits)?
+                }
+                None if arg == "--sandbox" => sandbox = true,
+                None if arg.starts_with("--allow-include=") => {
+                    let dir = PathBuf::from(&arg["--allow-include=".len()..]);
+                    let roots = options
+                        .capabilities
+                        .include_roots
+                        .get_or_insert_with(Vec::new);
+                    roots.push(dir);
+                }
+                None if arg.starts_with("--allow-io=") => {
+                    let word = &arg["--allow-io=".len()..];
+                    if !IO_WORDS.contains(&word) {
+                        let words: Vec<String> =
+                            IO_WORDS.iter().map(|w| format!("`{}`", w)).collect();
+                        return Err(
+                            CliError::UnknownIoWord(word.to_string(), words.join(", ")).into()
+                        );
+                    }
+                    let words = options.capabilities.io_words.get_or_insert_with(Vec::new);
+                    words.push(word.to_string());
+                }
+                None if arg == "--allow-env" => options.capabilities.env = Some(true),
+                None if arg == "--allow-exit" => options.capabilities.exit = Some(true),
+                None if arg == "--check" => options.check_formatting = true,
+                None if arg.starts_with("--filter=") => {
+                    options.test_filter = Some(arg["--filter=".len()..].to_string())
+                }
+                None => rest.push(arg),
+            }
+        }
+        drop(drained);
+        *args = rest;
+        if sandbox {
+            options.capabilities = options.capabilities.sandboxed();
+        }
+
+        Ok(options)
+    }
+
+    // A preprocessor for `source`, read from `input`, that knows where it can
+    // include files from
+    pub fn preprocessor(&self, input: &Input, source: &str) -> PreProcessor {
+        let preprocessor = PreProcessor::new(source)
+            .with_include_dirs(self.include_dirs.clone())
+            .with_capabilities(self.capabilities.clone());
+        match input.path() {
+            Some(path) => preprocessor.with_path(path),
+            None => preprocessor,
+        }
+    }
+
+    // Warnings and test failures don't stop anything, so they're printed as soon
+    // as we have them
+    pub fn print_report(&self, warning: &Report, file_name: &str) {
+        if self.json_messages {
+            json::print_report(warning, file_name);
+        } else {
+            eprintln!("{:?}", warning);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Check,
+    Repl,
+    Fmt,
+    // print what the preprocessor turns the program into
+    Expand,
+    // dump what the lexer and parser make of the program
+    Tokens,
+    Ops,
+    Test,
+    // step through the program
+    Debug,
+    // a language server for editors
+    Lsp,
+    // where a macro or box is defined, and everywhere it's used
+    Def(String),
+    Refs(String),
+}
+
+static COMMANDS: &[(&str, Command)] = &[
+    ("run", Command::Run),
+    ("check", Command::Check),
+    ("repl", Command::Repl),
+    ("fmt", Command::Fmt),
+    ("expand", Command::Expand),
+    ("tokens", Command::Tokens),
+    ("ops", Command::Ops),
+    ("test", Command::Test),
+    ("debug", Command::Debug),
+    ("lsp", Command::Lsp),
+    ("def", Command::Def(String::new())),
+    ("refs", Command::Refs(String::new())),
+];
+
+// Where the program comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(String),
+    // `-`
+    Stdin,
+    // `-e '<code>'`
+    Code(String),
+}
+
+impl Input {
+    // What to call the program in diagnostics
+    pub fn name(&self) -> &str {
+        match self {
+            Input::File(file_name) => file_name,
+            Input::Stdin => "<stdin>",
+            Input::Code(_) => "<-e>",
+        }
+    }
+
+    // `None` for programs that aren't in a file, whose includes are relative to
+    // the current directory
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Input::File(file_name) => Some(Path::new(file_name)),
+            _ => None,
+        }
+    }
+
+    pub fn read(&self) -> Result<String> {
+        match self {
+            Input::File(file_name) => read_file(file_name),
+            Input::Stdin => {
+                let mut source = String::new();
+                match std::io::stdin().read_to_string(&mut source) {
+                    Ok(_) => Ok(source),
+                    Err(_) => Err(ParseError::CannotReadFile(self.name().to_string()).into()),
+                }
+            }
+            Input::Code(code) => Ok(code.clone()),
+        }
+    }
+}
+
+// `roth [command] [file | - | -e '<code>']`, with the flags already taken out by
+// `Options::parse`. Without a command a program is run, and without anything
+// at all we start the repl. `def` and `refs` take a name before the program.
+pub fn parse_command(args: &[String]) -> Result<(Command, Option<Input>)> {
+    let args = args.get(1..).unwrap_or_default();
+
+    let (command, rest) = match args.split_first() {
+        None => return Ok((Command::Repl, None)),
+        Some((first, rest)) => match COMMANDS.iter().find(|(name, _)| name == first) {
+            Some((_, command)) => (command.clone(), rest),
+            None => (Command::Run, args),
+        },
+    };
+
+    let (command, rest) = match (command, rest.split_first()) {
+        (Command::Def(_), Some((name, rest))) if !name.starts_with('-') => {
+            (Command::Def(name.clone()), rest)
+        }
+        (Command::Refs(_), Some((name, rest))) if !name.starts_with('-') => {
+            (Command::Refs(name.clone()), rest)
+        }
+        (command @ (Command::Def(_) | Command::Refs(_)), _) => {
+            return Err(CliError::MissingName(command_name(&command)).into())
+        }
+        (command, _) => (command, rest),
+    };
+
+    let input = match rest {
+        // tests are looked for in the current directory by default
+        [] if matches!(command, Command::Repl | Command::Test | Command::Lsp) => {
+            return Ok((command, None))
+        }
+        [] => return Err(CliError::MissingInput(command_name(&command)).into()),
+        [flag, code] if flag == "-e" => Input::Code(code.clone()),
+        [dash] if dash == "-" => Input::Stdin,
+        [file_name]
+            if !file_name.starts_with('-') && !matches!(command, Command::Repl | Command::Lsp) =>
+        {
+            Input::File(file_name.clone())
+        }
+        _ => return Err(CliError::UnexpectedArguments(rest.join(" ")).into()),
+    };
+
+    Ok((command, Some(input)))
+}
+
+fn command_name(command: &Command) -> String {
+    COMMANDS
+        .iter()
+        .find(|(_, c)| std::mem::discriminant(c) == std::mem::discriminant(command))
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_default()
+}
+
+static LIMITS: &[&str] = &["--max-steps", "--max-time", "--max-stack", "--max-memory"];
+
+// `--max-time` is in milliseconds, and `--max-memory` in bytes
+fn parse_limit(arg: &str, limits: &mut Limits) -> Result<()> {
+    let (flag, value) = arg.split_once('=').unwrap_or((arg, ""));
+    let number: u64 = match value.parse() {
+        Ok(number) => number,
+        Err(_) => return Err(CliError::InvalidLimit(flag.to_string(), value.to_string()).into()),
+    };
+
+    match flag {
+        "--max-steps" => limits.steps = Some(number),
+        "--max-time" => limits.time = Some(Duration::from_millis(number)),
+        "--max-stack" => limits.stack_depth = Some(number as usize),
+        _ => limits.memory = Some(number as usize),
+    }
+
+    Ok(())
+}
+
+fn read_file(file_name: &str) -> Result<String> {
+    std::fs::read_to_string(file_name)
+        .map_err(|_| ParseError::CannotReadFile(file_name.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse_command, Options};
+    use expect_test::expect;
+
+    fn parse(args: &str) -> String {
+        let mut args: Vec<String> = args.split(' ').map(String::from).collect();
+        match Options::parse(&mut args).and_then(|_| parse_command(&args)) {
+            Ok(parsed) => format!("{:?}", parsed),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let actual: Vec<String> = [
+            "roth",
+            "roth foo.roth",
+            "roth --debug foo.roth",
+            "roth check --deny=warnings foo.roth",
+            "roth tokens -",
+            "roth -e --debug",
+            "roth ops",
+            "roth run a.roth b.roth",
+            "roth refs sq lib.roth",
+            "roth def lib.roth",
+            "roth --max-steps=lots foo.roth",
+            "roth --sandbox --allow-io=exit foo.roth",
+            "roth -I lib -Ivendor foo.roth",
+            "roth foo.roth -I",
+        ]
+        .iter()
+        .map(|args| parse(args))
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "(Repl, None)",
+                "(Run, Some(File(\"foo.roth\")))",
+                "(Run, Some(File(\"foo.roth\")))",
+                "(Check, Some(File(\"foo.roth\")))",
+                "(Tokens, Some(Stdin))",
+                "(Run, Some(Code(\"--debug\")))",
+                "`roth ops` needs a program",
+                "Unexpected arguments `a.roth b.roth`",
+                "(Refs(\"sq\"), Some(File(\"lib.roth\")))",
+                "`roth def` needs a program",
+                "`--max-steps` needs a number, not `lots`",
+                "`exit` isn't an I/O word",
+                "(Run, Some(File(\"foo.roth\")))",
+                "`-I` needs a directory",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_capability_flags() {
+        let actual: Vec<String> = [
+            "",
+            "--sandbox",
+            "--sandbox --allow-env",
+            "--sandbox --allow-exit",
+        ]
+        .iter()
+        .map(|flags| {
+            let mut args: Vec<String> = flags.split_whitespace().map(String::from).collect();
+            let capabilities = Options::parse(&mut args).unwrap().capabilities;
+            format!(
+                "{:?}: env {:?}, exit {:?}",
+                flags, capabilities.env, capabilities.exit
+            )
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "\"\": env None, exit None",
+                "\"--sandbox\": env Some(false), exit Some(false)",
+                "\"--sandbox --allow-env\": env Some(true), exit Some(false)",
+                "\"--sandbox --allow-exit\": env Some(false), exit Some(true)",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
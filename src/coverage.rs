@@ -0,0 +1,367 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use miette::{Result, SourceSpan};
+
+use crate::{
+    error::CliError,
+    op::{Op, OpKind},
+    preprocessor::{origin_of, Expansion, LineMap},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverageOutput {
+    // `--coverage`, the program with hit counts on stderr
+    Annotated,
+    // `--coverage=<file>`, an LCOV tracefile
+    Lcov(PathBuf),
+}
+
+// Which ops of a program ran and which way its `if`s went. Ops from macros count
+// for the line they're written on in the macro's definition.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    // the program as it was given, and as it's written
+    name: String,
+    source: String,
+    lines: LineMap,
+    expansions: Vec<Expansion>,
+    // every op that could run, by (offset, len) of its span
+    hits: HashMap<(usize, usize), usize>,
+    // how many times each `if` was (true, false)
+    branches: HashMap<(usize, usize), (usize, usize)>,
+}
+
+impl Coverage {
+    // `ops` are the ones that could run, which don't have to be all of the
+    // program's (i.e. when running its tests)
+    pub fn new(
+        name: &str,
+        source: &str,
+        lines: LineMap,
+        expansions: Vec<Expansion>,
+        ops: &VecDeque<Op>,
+    ) -> Self {
+        let mut coverage = Self {
+            name: name.to_string(),
+            source: source.to_string(),
+            lines,
+            expansions,
+            hits: HashMap::new(),
+            branches: HashMap::new(),
+        };
+        for op in ops {
+            // `end` only shows up when its block ran, which the other ops already say
+            if !matches!(op.kind, OpKind::End) {
+                coverage.hits.insert(key(&op.span), 0);
+            }
+            if matches!(op.kind, OpKind::If) {
+                coverage.branches.insert(key(&op.span), (0, 0));
+            }
+        }
+
+        coverage
+    }
+
+    pub fn hit(&mut self, span: &SourceSpan) {
+        if let Some(hits) = self.hits.get_mut(&key(span)) {
+            *hits += 1;
+        }
+    }
+
+    pub fn branch(&mut self, span: &SourceSpan, taken: bool) {
+        if let Some((when_true, when_false)) = self.branches.get_mut(&key(span)) {
+            match taken {
+                true => *when_true += 1,
+                false => *when_false += 1,
+            }
+        }
+    }
+
+    // Add what another run of the same program found
+    pub fn merge(&mut self, other: &Coverage) {
+        for (span, hits) in &other.hits {
+            *self.hits.entry(*span).or_default() += hits;
+        }
+        for (span, (when_true, when_false)) in &other.branches {
+            let branch = self.branches.entry(*span).or_default();
+            branch.0 += when_true;
+            branch.1 += when_false;
+        }
+    }
+
+    // (file as shown, 1-based line, 1-based column) an op was written at
+    fn position_of(&self, offset: usize) -> Option<(String, usize, usize)> {
+        let (file, line, column) = self
+            .lines
+            .position_of(origin_of(&self.expansions, offset))?;
+        let file = match file {
+            None => self.name.clone(),
+            // next to the program, like `roth refs` shows them
+            Some(file) => match Path::new(&self.name).parent() {
+                Some(dir) => dir
+                    .join(file.trim_start_matches("./"))
+                    .display()
+                    .to_string(),
+                None => file.to_string(),
+            },
+        };
+
+        Some((file, line, column))
+    }
+}
+
+// What ran in one file, from however many programs use it
+#[derive(Debug, Default)]
+struct FileCoverage {
+    text: String,
+    // by (line, column), since a macro's ops are run from every place it's used
+    ops: BTreeMap<(usize, usize), usize>,
+    // by (line, column) of the `if`: how many times it ran, was true and was false
+    branches: BTreeMap<(usize, usize), (usize, usize, usize)>,
+}
+
+impl FileCoverage {
+    // Hits of the most run op on every line with ops
+    fn lines(&self) -> BTreeMap<usize, usize> {
+        let mut lines = BTreeMap::new();
+        for (&(line, _), &hits) in &self.ops {
+            let line_hits = lines.entry(line).or_default();
+            *line_hits = hits.max(*line_hits);
+        }
+
+        lines
+    }
+
+    fn summary(&self) -> (usize, usize, usize, usize) {
+        let lines = self.lines();
+        let lines_hit = lines.values().filter(|hits| **hits > 0).count();
+        let branches_hit: usize = self
+            .branches
+            .values()
+            .map(|(_, when_true, when_false)| {
+                usize::from(*when_true > 0) + usize::from(*when_false > 0)
+            })
+            .sum();
+
+        (
+            lines.len(),
+            lines_hit,
+            self.branches.len() * 2,
+            branches_hit,
+        )
+    }
+}
+
+fn files(coverages: &[Coverage]) -> BTreeMap<String, FileCoverage> {
+    let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+    for coverage in coverages {
+        for (&(offset, len), &hits) in &coverage.hits {
+            let (file, line, column) = match coverage.position_of(offset) {
+                Some(position) => position,
+                None => continue,
+            };
+            let covered = files.entry(file.clone()).or_insert_with(|| FileCoverage {
+                text: text_of(coverage, &file),
+                ..FileCoverage::default()
+            });
+
+            *covered.ops.entry((line, column)).or_default() += hits;
+            if let Some((when_true, when_false)) = coverage.branches.get(&(offset, len)) {
+                let branch = covered.branches.entry((line, column)).or_default();
+                branch.0 += hits;
+                branch.1 += when_true;
+                branch.2 += when_false;
+            }
+        }
+    }
+
+    files
+}
+
+// The program is kept around, since it could've come from stdin. Included files
+// are read again, from where they're shown as being.
+fn text_of(coverage: &Coverage, file: &str) -> String {
+    if file == coverage.name {
+        return coverage.source.clone();
+    }
+
+    std::fs::read_to_string(file).unwrap_or_default()
+}
+
+// Print the annotated programs, or write the LCOV file
+pub fn report(output: &CoverageOutput, coverages: &[Coverage]) -> Result<()> {
+    match output {
+        CoverageOutput::Annotated => eprint!("{}", annotate(coverages)),
+        CoverageOutput::Lcov(file) => {
+            if std::fs::write(file, lcov(coverages)).is_err() {
+                return Err(CliError::CannotWriteFile(file.display().to_string()).into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// An LCOV tracefile, which `genhtml` and most CI services read
+pub fn lcov(coverages: &[Coverage]) -> String {
+    let mut lcov = String::new();
+    for (file, covered) in files(coverages) {
+        let _ = writeln!(lcov, "TN:\nSF:{}", file);
+        for (block, ((line, _), (ran, when_true, when_false))) in
+            covered.branches.iter().enumerate()
+        {
+            for (branch, taken) in [when_true, when_false].into_iter().enumerate() {
+                // `-` means the `if` itself never ran
+                let taken = match ran {
+                    0 => String::from("-"),
+                    _ => taken.to_string(),
+                };
+                let _ = writeln!(lcov, "BRDA:{},{},{},{}", line, block, branch, taken);
+            }
+        }
+        let (lines, lines_hit, branches, branches_hit) = covered.summary();
+        let _ = writeln!(lcov, "BRF:{}\nBRH:{}", branches, branches_hit);
+        for (line, hits) in covered.lines() {
+            let _ = writeln!(lcov, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(lcov, "LF:{}\nLH:{}\nend_of_record", lines, lines_hit);
+    }
+
+    lcov
+}
+
+// Every file with how many times each line ran (`#####` for lines that never
+// did), and a note under every `if` that didn't go both ways
+pub fn annotate(coverages: &[Coverage]) -> String {
+    let mut annotated = String::new();
+    for (file, covered) in files(coverages) {
+        let (lines, lines_hit, branches, branches_hit) = covered.summary();
+        let hits_by_line = covered.lines();
+        let _ = writeln!(
+            annotated,
+            "{}: {}/{} lines, {}/{} branches",
+            file, lines_hit, lines, branches_hit, branches
+        );
+
+        for (idx, text) in covered.text.lines().enumerate() {
+            let line = idx + 1;
+            let hits = match hits_by_line.get(&line) {
+                None => String::from("-"),
+                Some(0) => String::from("#####"),
+                Some(hits) => hits.to_string(),
+            };
+            let _ = writeln!(annotated, "{:>8}: {}", hits, text);
+
+            for ((_, column), (ran, when_true, when_false)) in
+                covered.branches.range((line, 0)..(line + 1, 0))
+            {
+                let never = match (ran, when_true, when_false) {
+                    (0, _, _) => "never ran",
+                    (_, 0, _) => "was never true",
+                    (_, _, 0) => "was never false",
+                    _ => continue,
+                };
+                let _ = writeln!(
+                    annotated,
+                    "{:>8}  {}^^ `if` {}",
+                    "",
+                    " ".repeat(column - 1),
+                    never
+                );
+            }
+        }
+        annotated.push('\n');
+    }
+
+    annotated
+}
+
+fn key(span: &SourceSpan) -> (usize, usize) {
+    (span.offset(), span.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        coverage::{annotate, lcov, Coverage},
+        lexer::Lexer,
+        parser::Parser,
+        preprocessor::PreProcessor,
+        runtime::Runtime,
+    };
+    use expect_test::expect;
+
+    #[test]
+    fn test_coverage() {
+        let source = "macro abs ( int -- int )
+  dup 0 < if
+    -1 *
+  end
+end
+macro never 1 2 end
+5 abs
+3 abs eq
+if \"same\" print end
+false if never end";
+        let mut preprocessor = PreProcessor::new(source);
+        let expanded_source = preprocessor.expand().unwrap();
+        let tokens = Lexer::new(&expanded_source).lex().unwrap();
+        let ops = Parser::new(tokens, expanded_source.clone())
+            .parse()
+            .unwrap();
+        let coverage = Coverage::new(
+            "c.roth",
+            source,
+            preprocessor.lines().clone(),
+            preprocessor.expansions().to_vec(),
+            &ops,
+        );
+
+        let mut runtime = Runtime::new(expanded_source, ops).with_coverage(coverage);
+        runtime.run().unwrap();
+        let coverages = [runtime.coverage().unwrap().clone()];
+
+        let expected = expect![[r######"
+            c.roth: 5/7 lines, 3/6 branches
+                   -: macro abs ( int -- int )
+                   2:   dup 0 < if
+                                ^^ `if` was never true
+               #####:     -1 *
+                   -:   end
+                   -: end
+               #####: macro never 1 2 end
+                   1: 5 abs
+                   1: 3 abs eq
+                   1: if "same" print end
+                      ^^ `if` was never true
+                   1: false if never end
+                            ^^ `if` was never true
+
+            TN:
+            SF:c.roth
+            BRDA:2,0,0,0
+            BRDA:2,0,1,2
+            BRDA:9,1,0,0
+            BRDA:9,1,1,1
+            BRDA:10,2,0,0
+            BRDA:10,2,1,1
+            BRF:6
+            BRH:3
+            DA:2,2
+            DA:3,0
+            DA:6,0
+            DA:7,1
+            DA:8,1
+            DA:9,1
+            DA:10,1
+            LF:7
+            LH:5
+            end_of_record
+        "######]];
+        expected.assert_eq(&format!("{}{}", annotate(&coverages), lcov(&coverages)));
+    }
+}
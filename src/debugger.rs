@@ -0,0 +1,508 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use miette::{Result, SourceSpan};
+
+use crate::{
+    cli::Options,
+    lexer::Lexer,
+    limits::Limits,
+    op::{Op, OpKind},
+    parser::Parser,
+    preprocessor::{origin_of, Expansion, LineMap, PreProcessor},
+    runtime::Runtime,
+};
+
+static HELP: &str = "\
+s, step              run the next op
+n, next              run the next op, and any macro it expands to
+c, continue          run until a breakpoint or the end of the program
+b, break <line>      stop before running <line> (or <file>:<line> for included files)
+b, break             list breakpoints
+d, delete <line>     remove a breakpoint
+q, quit              stop debugging
+an empty line does the last command again
+";
+
+// `roth debug`: run the program one op at a time
+pub fn debug(preprocessor: PreProcessor, name: &str, options: &Options) -> Result<()> {
+    let mut debugger = load(preprocessor, name, options)?;
+
+    println!("type `help` for a list of commands");
+    print!("{}", debugger.status());
+    let stdin = io::stdin();
+    while !debugger.finished {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        match debugger.command(line.trim())? {
+            Some(output) => print!("{}", output),
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+// The program, ready to step through under the same limits and capabilities
+// it would run with
+fn load(mut preprocessor: PreProcessor, name: &str, options: &Options) -> Result<Debugger> {
+    let expanded_source = preprocessor.expand()?;
+
+    let tokens = Lexer::new(expanded_source.as_str()).lex()?;
+    let ops = Parser::new(tokens, expanded_source.clone())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_expansions(preprocessor.expansions().to_vec())
+        .parse()?;
+    let runtime = Runtime::new(expanded_source.clone(), ops)
+        .with_expansions(preprocessor.expansions().to_vec())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_effect_checks()
+        .with_macro_names(preprocessor.macro_names())
+        .with_scopes(preprocessor.scopes().clone())
+        // time spent stopped at a breakpoint isn't the program's
+        .with_limits(Limits {
+            time: None,
+            ..options.limits
+        })
+        .with_capabilities(options.capabilities.clone());
+
+    Ok(Debugger::new(
+        name,
+        expanded_source,
+        runtime,
+        preprocessor.lines().clone(),
+        preprocessor.expansions().to_vec(),
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Breakpoint {
+    // `None` for the program itself
+    file: Option<String>,
+    line: usize,
+}
+
+impl Breakpoint {
+    // `12` or `lib.roth:3`
+    fn parse(at: &str) -> Option<Self> {
+        match at.rsplit_once(':') {
+            Some((file, line)) => Some(Breakpoint {
+                file: Some(file.to_string()),
+                line: line.parse().ok()?,
+            }),
+            None => Some(Breakpoint {
+                file: None,
+                line: at.parse().ok()?,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+struct Debugger {
+    name: String,
+    source: String,
+    runtime: Runtime,
+    lines: LineMap,
+    expansions: Vec<Expansion>,
+    breakpoints: Vec<Breakpoint>,
+    last_command: String,
+    // where the op we ran last came from, so a breakpoint stops once per visit to its line
+    last_location: Option<Breakpoint>,
+    finished: bool,
+}
+
+impl Debugger {
+    fn new(
+        name: &str,
+        source: String,
+        runtime: Runtime,
+        lines: LineMap,
+        expansions: Vec<Expansion>,
+    ) -> Self {
+        let mut debugger = Self {
+            name: name.to_string(),
+            source,
+            runtime,
+            lines,
+            expansions,
+            breakpoints: Vec::new(),
+            last_command: String::new(),
+            last_location: None,
+            finished: false,
+        };
+        // a program without any ops is done before it starts
+        debugger.finished = debugger.runtime.next_op().is_none();
+
+        debugger
+    }
+
+    // Run a command, returning what to show, or `None` to stop debugging.
+    // Errors from the program end the session.
+    fn command(&mut self, input: &str) -> Result<Option<String>> {
+        let input = if input.is_empty() {
+            self.last_command.clone()
+        } else {
+            input.to_string()
+        };
+        self.last_command = input.clone();
+
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let output = match words.as_slice() {
+            ["s" | "step"] => {
+                self.step()?;
+                self.status()
+            }
+            ["n" | "next"] => {
+                self.next()?;
+                self.status()
+            }
+            ["c" | "continue"] => {
+                self.resume()?;
+                self.status()
+            }
+            ["b" | "break"] => self
+                .breakpoints
+                .iter()
+                .map(|breakpoint| format!("breakpoint at {}\n", breakpoint))
+                .collect(),
+            ["b" | "break", at] => match Breakpoint::parse(at) {
+                Some(breakpoint) => {
+                    let output = format!("breakpoint at {}\n", breakpoint);
+                    self.breakpoints.push(breakpoint);
+                    output
+                }
+                None => String::from("expected a line, like `break 12` or `break lib.roth:3`\n"),
+            },
+            ["d" | "delete", at] => match Breakpoint::parse(at) {
+                Some(breakpoint) if self.breakpoints.contains(&breakpoint) => {
+                    self.breakpoints.retain(|b| *b != breakpoint);
+                    format!("removed the breakpoint at {}\n", breakpoint)
+                }
+                _ => format!("there's no breakpoint at `{}`\n", at),
+            },
+            ["q" | "quit"] => return Ok(None),
+            ["h" | "help"] => HELP.to_string(),
+            _ => format!("unknown command `{}`, try `help`\n", input),
+        };
+
+        Ok(Some(output))
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.last_location = self.runtime.next_op().and_then(|op| self.location(op));
+        self.runtime.step()?;
+        if self.runtime.next_op().is_none() {
+            self.runtime.finish()?;
+            self.finished = true;
+        }
+
+        Ok(())
+    }
+
+    // Step over macros instead of into them
+    fn next(&mut self) -> Result<()> {
+        let depth = match self.runtime.next_op() {
+            Some(op) => self.depth(&op.span),
+            None => return Ok(()),
+        };
+
+        self.step()?;
+        while let Some(op) = self.runtime.next_op() {
+            if self.depth(&op.span) <= depth {
+                break;
+            }
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.step()?;
+        while let Some(op) = self.runtime.next_op() {
+            if matches!(op.kind, OpKind::Breakpoint) {
+                break;
+            }
+            let location = self.location(op);
+            if location != self.last_location
+                && location.is_some_and(|at| self.breakpoints.contains(&at))
+            {
+                break;
+            }
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    // How many macros the op at `span` is inside of
+    fn depth(&self, span: &SourceSpan) -> usize {
+        self.expansions
+            .iter()
+            .filter(|e| contains(&e.span, span))
+            .count()
+    }
+
+    fn location(&self, op: &Op) -> Option<Breakpoint> {
+        self.lines
+            .line_of(op.span.offset())
+            .map(|(file, line)| Breakpoint {
+                file: file.map(String::from),
+                line,
+            })
+    }
+
+    // The op that runs next, the stack and the boxes
+    fn status(&self) -> String {
+        let mut status = String::new();
+
+        match self.runtime.next_op() {
+            None => status.push_str("the program finished\n"),
+            Some(op) => {
+                let file = match self.location(op) {
+                    Some(Breakpoint {
+                        file: Some(file),
+                        line,
+                    }) => format!("{}:{}", file, line),
+                    Some(Breakpoint { file: None, line }) => format!("{}:{}", self.name, line),
+                    None => self.name.clone(),
+                };
+                let _ = write!(status, "at {}", file);
+                // innermost macro first
+                for expansion in self.expansions.iter().rev() {
+                    if contains(&expansion.span, &op.span) {
+                        let _ = write!(status, ", in `{}`", expansion.name);
+                    }
+                }
+                status.push('\n');
+                status.push_str(&self.snippet(&op.span));
+            }
+        }
+
+        let stack: Vec<String> = self.runtime.stack().iter().map(|v| v.literal()).collect();
+        let _ = writeln!(status, "stack: [{}]", stack.join(", "));
+        for (name, val_type, val) in self.runtime.boxes() {
+            let _ = writeln!(status, "box {} {} = {}", val_type, name, val.literal());
+        }
+
+        status
+    }
+
+    // The line `span` was written on, with the span underlined. Ops from macro
+    // bodies and included files show up where they're written, not where they
+    // ended up in the expanded program.
+    fn snippet(&self, span: &SourceSpan) -> String {
+        let (line, column) = match self.written_line(span) {
+            Some(written) => written,
+            None => self.expanded_line(span),
+        };
+
+        let width = line.chars().count();
+        let column = column.min(width);
+        let underline = "^".repeat(span.len().clamp(1, width.saturating_sub(column).max(1)));
+        format!("    {}\n    {}{}\n", line, " ".repeat(column), underline)
+    }
+
+    // (line, 0-based column) in the file `span` came from
+    fn written_line(&self, span: &SourceSpan) -> Option<(String, usize)> {
+        let origin = origin_of(&self.expansions, span.offset());
+        let (file, line, column) = self.lines.position_of(origin)?;
+        let line = self.lines.text_of(file)?.lines().nth(line - 1)?;
+
+        Some((line.to_string(), column - 1))
+    }
+
+    // (line, 0-based column) in the expanded program, for when we don't know
+    // where `span` came from
+    fn expanded_line(&self, span: &SourceSpan) -> (String, usize) {
+        // spans count chars
+        let chars: Vec<char> = self.source.chars().collect();
+        let offset = span.offset().min(chars.len());
+        let start = chars[..offset]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |idx| idx + 1);
+        let end = chars[offset..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(chars.len(), |idx| offset + idx);
+
+        (chars[start..end].iter().collect(), offset - start)
+    }
+}
+
+fn contains(outer: &SourceSpan, inner: &SourceSpan) -> bool {
+    outer.offset() <= inner.offset() && inner.offset() < outer.offset() + outer.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{cli::Options, debugger::load, preprocessor::PreProcessor};
+    use expect_test::expect;
+
+    #[test]
+    fn test_debugger() {
+        let source = "macro sq dup * end
+box type::int x
+3 sq x pack
+1 breakpoint 2
+x unpack +";
+        let options = Options::parse(&mut Vec::new()).unwrap();
+        let mut debugger = load(PreProcessor::new(source), "sq.roth", &options).unwrap();
+
+        let mut actual = debugger.status();
+        for command in ["b 5", "s", "s", "s", "n", "c", "", "c"] {
+            actual.push_str(&format!("> {}\n", command));
+            actual.push_str(&debugger.command(command).unwrap().unwrap());
+        }
+
+        let expected = expect![[r#"
+            at sq.roth:2
+                box type::int x
+                ^^^
+            stack: []
+            > b 5
+            breakpoint at line 5
+            > s
+            at sq.roth:3
+                3 sq x pack
+                ^
+            stack: []
+            box type::int x = 0
+            > s
+            at sq.roth:3, in `sq`
+                macro sq dup * end
+                         ^^^
+            stack: [3]
+            box type::int x = 0
+            > s
+            at sq.roth:3, in `sq`
+                macro sq dup * end
+                             ^
+            stack: [3, 3]
+            box type::int x = 0
+            > n
+            at sq.roth:3
+                3 sq x pack
+                     ^
+            stack: [9]
+            box type::int x = 0
+            > c
+            at sq.roth:4
+                1 breakpoint 2
+                  ^^^^^^^^^^
+            stack: [1]
+            box type::int x = 9
+            > 
+            at sq.roth:5
+                x unpack +
+                ^
+            stack: [1, 2]
+            box type::int x = 9
+            > c
+            the program finished
+            stack: [1, 11]
+            box type::int x = 9
+        "#]];
+
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_debugger_include() {
+        let dir = std::env::temp_dir().join(format!("roth-debug-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/two.roth"), "// two\n\n  2 drop\n").unwrap();
+
+        let source = "1\ninclude(\"lib/two.roth\")\n3";
+        let path = dir.join("main.roth");
+        fs::write(&path, source).unwrap();
+        let options = Options::parse(&mut Vec::new()).unwrap();
+        let preprocessor = PreProcessor::new(source).with_path(&path);
+        let mut debugger = load(preprocessor, "main.roth", &options).unwrap();
+
+        let mut actual = debugger.status();
+        for command in ["s", "s", "s"] {
+            actual.push_str(&format!("> {}\n", command));
+            actual.push_str(&debugger.command(command).unwrap().unwrap());
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        let expected = expect![[r#"
+            at main.roth:1
+                1
+                ^
+            stack: []
+            > s
+            at lib/two.roth:3
+                  2 drop
+                  ^
+            stack: [1]
+            > s
+            at lib/two.roth:3
+                  2 drop
+                    ^^^^
+            stack: [1, 2]
+            > s
+            at main.roth:3
+                3
+                ^
+            stack: [1]
+        "#]];
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_debugger_limits() {
+        let actual: Vec<String> = [
+            ("1 print", "--sandbox"),
+            ("1 print", "--allow-io=print"),
+            ("false until false end", "--max-steps=20"),
+            ("1 2 3", "--max-stack=2"),
+        ]
+        .iter()
+        .map(|(source, flag)| {
+            let options = Options::parse(&mut vec![flag.to_string()]).unwrap();
+            let mut debugger = load(PreProcessor::new(source), "limits.roth", &options).unwrap();
+            let outcome = match debugger.command("c") {
+                Ok(_) => String::from("ok"),
+                Err(e) => e.code().unwrap().to_string(),
+            };
+            format!("{} {}: {}", flag, source, outcome)
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "--sandbox 1 print: roth::word_not_allowed",
+                "--allow-io=print 1 print: ok",
+                "--max-steps=20 false until false end: roth::step_limit_exceeded",
+                "--max-stack=2 1 2 3: roth::stack_limit_exceeded",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -1,7 +1,166 @@
-use miette::{Diagnostic, SourceSpan};
+use miette::{Diagnostic, Report, Result, SourceSpan};
 use thiserror::Error;
 
-use crate::val::ValType;
+use crate::{preprocessor::LineMap, val::ValType};
+
+// Everything that went wrong in one go, so fixing a file isn't one error at a time
+#[derive(Error, Debug)]
+#[error("Found {} problems", .0.len())]
+pub struct Errors(pub Vec<Report>);
+
+// written by hand since the derive can't see through `Report`
+impl Diagnostic for Errors {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new("roth::multiple_errors"))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.0.iter().map(|e| -> &'a dyn Diagnostic { &**e }),
+        ))
+    }
+}
+
+impl Errors {
+    // A single error is reported on its own, so it can still be downcast
+    pub fn into_result(mut errors: Vec<Report>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Errors(errors).into()),
+        }
+    }
+}
+
+// A lint that was turned into an error with `--deny`
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct Denied(pub Report);
+
+impl Diagnostic for Denied {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.0.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(miette::Severity::Error)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.0.help()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        self.0.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.0.labels()
+    }
+}
+
+// An error about the expanded program, along with where that came from, so
+// `--message-format=json` can point at what was written
+#[derive(Error, Debug)]
+#[error("{error}")]
+pub struct Located {
+    pub error: Report,
+    pub lines: LineMap,
+}
+
+impl Located {
+    pub fn wrap(lines: &LineMap) -> impl Fn(Report) -> Report + '_ {
+        |error| {
+            Located {
+                error,
+                lines: lines.clone(),
+            }
+            .into()
+        }
+    }
+}
+
+impl Diagnostic for Located {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.error.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        self.error.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.error.help()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        self.error.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.error.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        self.error.related()
+    }
+}
+
+// A runtime error, along with the macro expansions and included files it
+// happened inside of
+#[derive(Error, Debug)]
+#[error("{error}")]
+pub struct Traced {
+    pub error: Report,
+    pub trace: Vec<TraceFrame>,
+}
+
+impl Diagnostic for Traced {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.error.code()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.error.help()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        self.error.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.error.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.trace
+                .iter()
+                .map(|frame| -> &'a dyn Diagnostic { frame }),
+        ))
+    }
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum TraceFrame {
+    #[error("in macro `{1}`")]
+    #[diagnostic(code(roth::trace), severity(Advice))]
+    Macro(
+        #[source_code] String,
+        String, // macro name
+        #[label("`{1}` was expanded here")] SourceSpan,
+        #[label("defined here")] SourceSpan,
+    ),
+
+    #[error("in `{1}`, line {2}")]
+    #[diagnostic(code(roth::trace), severity(Advice))]
+    Include(
+        #[source_code] String,
+        String, // file name
+        usize,  // line in that file
+        #[label("this came from `{1}`")] SourceSpan,
+    ),
+}
 
 #[derive(Error, Debug, Diagnostic)]
 pub enum ParseError {
@@ -13,13 +172,27 @@ pub enum ParseError {
     CannotReadFile(String),
 
     #[error("unterminated string literal")]
-    #[diagnostic(code(roth::unterminated_string))]
-    UnterminatedStringLiteral,
+    #[diagnostic(
+        code(roth::unterminated_string),
+        help("strings have to be closed with a `\"`")
+    )]
+    UnterminatedStringLiteral(
+        #[source_code] String,
+        #[label("this string is never closed")] SourceSpan,
+    ),
+
+    #[error("Unknown type")]
+    #[diagnostic(code(roth::unknown_type), help("{1}"))]
+    UnknownType(
+        #[source_code] String,
+        String, // help, with a suggestion if there's a close enough type
+        #[label("not a type")] SourceSpan,
+    ),
 
     #[error("Can't include non-existent file")]
     #[diagnostic(
         code(roth::include_file_doesnt_exist),
-        help("Make sure there exists a file at `{1}`")
+        help("Make sure there exists a file at `{1}`, next to the file including it or in a directory from `-I` or `ROTH_PATH`")
     )]
     CantIncludeNonExistentFile(
         #[source_code] String,
@@ -27,6 +200,56 @@ pub enum ParseError {
         #[label("This include statement points to a non existent file")] SourceSpan,
     ),
 
+    #[error("Files include each other: {1}")]
+    #[diagnostic(
+        code(roth::include_cycle),
+        help("a file can't include one that includes it back. Move what they share into a third file")
+    )]
+    IncludeCycle(
+        #[source_code] String,
+        String, // the files, outermost first
+        #[label("this includes a file that's already being included")] SourceSpan,
+    ),
+
+    #[error("`{1}` isn't a module")]
+    #[diagnostic(
+        code(roth::not_a_module),
+        help("only files that start with `module <name>` can be imported. Use `include` to pull in a file as it is")
+    )]
+    NotAModule(
+        #[source_code] String,
+        String, // file name
+        #[label("this imports it")] SourceSpan,
+    ),
+
+    #[error("`{1}` isn't exported")]
+    #[diagnostic(code(roth::not_exported), help("{2}"))]
+    NotExported(
+        #[source_code] String,
+        String,
+        String, // help, with what the module does export
+        #[label("only the module itself can use this")] SourceSpan,
+    ),
+
+    #[error("No module called `{1}` here")]
+    #[diagnostic(code(roth::unknown_module), help("{2}"))]
+    UnknownModule(
+        #[source_code] String,
+        String,
+        String, // help, with a suggestion if there's a close enough module
+        #[label("not imported")] SourceSpan,
+    ),
+
+    #[error("`export` has to go in front of a macro or box in a module")]
+    #[diagnostic(
+        code(roth::misplaced_export),
+        help("i.e. `export macro square dup * end`, in a file that starts with `module <name>`")
+    )]
+    MisplacedExport(
+        #[source_code] String,
+        #[label("nothing to export here")] SourceSpan,
+    ),
+
     #[error("Unable to open or read file inside `include` statement")]
     #[diagnostic(
         code(roth::cant_open_include_file),
@@ -39,6 +262,15 @@ pub enum ParseError {
         SourceSpan,
     ),
 
+    #[error("Not allowed to include `{1}`")]
+    #[diagnostic(code(roth::include_not_allowed), help("{2}"))]
+    IncludeNotAllowed(
+        #[source_code] String,
+        String, // file name
+        String, // help, with the directories that can be included from
+        #[label("this file is outside of what the program can include")] SourceSpan,
+    ),
+
     #[error("Can't create a box without a type")]
     #[diagnostic(
         code(roth::boxes_need_types),
@@ -59,6 +291,71 @@ pub enum ParseError {
         #[label("No name found for this box definition")] SourceSpan,
     ),
 
+    #[error("Can't create a macro without a name")]
+    #[diagnostic(
+        code(roth::macros_need_names),
+        help("Create a macro with the syntax `macro foo ... end`")
+    )]
+    MacrosNeedNames(
+        #[source_code] String,
+        #[label("No name found for this macro definition")] SourceSpan,
+    ),
+
+    #[error("Unclosed macro definition")]
+    #[diagnostic(
+        code(roth::unclosed_macro),
+        help("This usually happens when you forget to close a macro with a corresponding 'end' token")
+    )]
+    UnclosedMacro(
+        #[source_code] String,
+        #[label("macro has no closing 'end' token")] SourceSpan,
+    ),
+
+    #[error("Tests need names")]
+    #[diagnostic(
+        code(roth::tests_need_names),
+        help("Write a test with the syntax `test \"name\" ... end`")
+    )]
+    TestsNeedNames(
+        #[source_code] String,
+        #[label("No name found for this test")] SourceSpan,
+    ),
+
+    #[error("Unclosed test")]
+    #[diagnostic(
+        code(roth::unclosed_test),
+        help(
+            "This usually happens when you forget to close a test with a corresponding 'end' token"
+        )
+    )]
+    UnclosedTest(
+        #[source_code] String,
+        #[label("test has no closing 'end' token")] SourceSpan,
+    ),
+
+    #[error("Malformed stack effect")]
+    #[diagnostic(
+        code(roth::malformed_stack_effect),
+        help("Stack effects look like `( int int -- int )`")
+    )]
+    MalformedStackEffect(
+        #[source_code] String,
+        #[label("this isn't a valid stack effect")] SourceSpan,
+    ),
+
+    #[error("Unknown type in stack effect")]
+    #[diagnostic(
+        code(roth::unknown_stack_effect_type),
+        help(
+            "`{1}` isn't a type. Try `int`, `str`, `bool`, `box<int>`, `box<str>` or `box<bool>`"
+        )
+    )]
+    UnknownStackEffectType(
+        #[source_code] String,
+        String, // the unknown type
+        #[label("unknown type")] SourceSpan,
+    ),
+
     #[error("Boxes cannot be created for this type")]
     #[diagnostic(
         code(roth::unboxable_type),
@@ -73,8 +370,16 @@ pub enum ParseError {
 #[derive(Error, Debug, Diagnostic)]
 pub enum RuntimeError {
     #[error("Empty stack")]
-    #[diagnostic(code(roth::empty_stack))]
-    EmptyStackError,
+    #[diagnostic(
+        code(roth::empty_stack),
+        help("this needs {1} value(s), but there were only {2} on the stack")
+    )]
+    EmptyStackError(
+        #[source_code] String,
+        usize, // needed
+        usize, // available
+        #[label("not enough values for this")] SourceSpan,
+    ),
 
     #[error("Can't add these types")]
     #[diagnostic(code(roth::invalid_add))]
@@ -175,6 +480,13 @@ pub enum RuntimeError {
         #[label("only boolean types work for if statements")] SourceSpan,
     ),
 
+    #[error("Only boolean values can be used for until loops")]
+    #[diagnostic(code(roth::untils_expect_booleans))]
+    UntilsExpectBooleans(
+        #[source_code] String,
+        #[label("only boolean types work for until loops")] SourceSpan,
+    ),
+
     #[error("Unclosed if statement")]
     #[diagnostic(
         code(roth::unclosed_if_statement),
@@ -202,6 +514,73 @@ pub enum RuntimeError {
         #[label("this assertion did not evaluate to `true`")] SourceSpan,
     ),
 
+    #[error("Assertion failed: values aren't equal")]
+    #[diagnostic(code(roth::assert_eq_failed), help("expected {1}, but got {2}"))]
+    AssertEqFailed(
+        #[source_code] String,
+        String, // expected
+        String, // actual
+        #[label("expected {1}")] SourceSpan,
+        #[label("got {2}")] SourceSpan,
+        #[label("asserted here")] SourceSpan,
+    ),
+
+    #[error("Assertion failed: values are equal")]
+    #[diagnostic(code(roth::assert_ne_failed), help("both values are {1}"))]
+    AssertNeFailed(
+        #[source_code] String,
+        String,
+        #[label("this is {1}")] SourceSpan,
+        #[label("and so is this")] SourceSpan,
+        #[label("asserted here")] SourceSpan,
+    ),
+
+    #[error("Assertion failed: wrong type")]
+    #[diagnostic(code(roth::assert_type_failed), help("expected a {1}, but got a {2}"))]
+    AssertTypeFailed(
+        #[source_code] String,
+        String, // expected
+        String, // actual
+        #[label("expected {1}")] SourceSpan,
+        #[label("got {2}")] SourceSpan,
+        #[label("asserted here")] SourceSpan,
+    ),
+
+    #[error("Assertion failed: wrong stack depth")]
+    #[diagnostic(
+        code(roth::assert_stack_depth_failed),
+        help("expected {1} value(s) on the stack, but there were {2}")
+    )]
+    AssertStackDepthFailed(
+        #[source_code] String,
+        i128,  // expected
+        usize, // actual
+        #[label("expected {1}")] SourceSpan,
+        #[label("found {2} value(s) here")] SourceSpan,
+    ),
+
+    #[error("Can't assert the type of this")]
+    #[diagnostic(
+        code(roth::invalid_assert_type),
+        help("`assert-type` needs a type (i.e. `type::int`) on top of the stack")
+    )]
+    InvalidAssertType(
+        #[source_code] String,
+        #[label("this isn't a type")] SourceSpan,
+        #[label("for this")] SourceSpan,
+    ),
+
+    #[error("Can't assert the stack depth with this")]
+    #[diagnostic(
+        code(roth::invalid_assert_stack_depth),
+        help("`assert-stack-depth` needs an int on top of the stack")
+    )]
+    InvalidAssertStackDepth(
+        #[source_code] String,
+        #[label("this isn't an int")] SourceSpan,
+        #[label("for this")] SourceSpan,
+    ),
+
     #[error("Can't assert this type")]
     #[diagnostic(
         code(roth::invalid_add),
@@ -252,12 +631,326 @@ pub enum RuntimeError {
     ),
 
     #[error("Unknown box")]
+    #[diagnostic(code(roth::unknown_box), help("{1}"))]
+    UnknownBox(
+        #[source_code] String,
+        String, // help, with a suggestion if there's a close enough name
+        #[label("no box with this name")] SourceSpan,
+    ),
+
+    #[error("`{1}` broke its stack effect")]
+    #[diagnostic(code(roth::stack_effect_violated), help("`{1}` is declared as {2}"))]
+    StackEffectViolated(
+        #[source_code] String,
+        String, // macro name
+        String, // the stack effect, and what actually happened
+        #[label("declared here")] SourceSpan,
+        #[label("while running this")] SourceSpan,
+    ),
+
+    #[error("Interrupted")]
+    #[diagnostic(code(roth::interrupted), help("evaluation was stopped with ctrl-c"))]
+    Interrupted(
+        #[source_code] String,
+        #[label("stopped before this")] SourceSpan,
+    ),
+
+    #[error("Not allowed to run `{1}`")]
     #[diagnostic(
-        code(roth::unknown_box),
-        help("couldn't find any boxes with this name. Is it a typo?")
+        code(roth::word_not_allowed),
+        help("the program is sandboxed. Let it run `{1}` with `--allow-io={1}`")
     )]
-    UnknownBox(
+    WordNotAllowed(
+        #[source_code] String,
+        String,
+        #[label("stopped before this")] SourceSpan,
+    ),
+
+    #[error("Ran more than {1} ops")]
+    #[diagnostic(
+        code(roth::step_limit_exceeded),
+        help("the program was stopped by `--max-steps`. Is there a loop that never ends?")
+    )]
+    StepLimitExceeded(
+        #[source_code] String,
+        u64,
+        #[label("stopped before this")] SourceSpan,
+    ),
+
+    #[error("Ran for more than {1}")]
+    #[diagnostic(
+        code(roth::time_limit_exceeded),
+        help("the program was stopped by `--max-time`. Is there a loop that never ends?")
+    )]
+    TimeLimitExceeded(
+        #[source_code] String,
+        String,
+        #[label("stopped before this")] SourceSpan,
+    ),
+
+    #[error("More than {1} values on the stack")]
+    #[diagnostic(
+        code(roth::stack_limit_exceeded),
+        help("the program was stopped by `--max-stack`")
+    )]
+    StackLimitExceeded(
+        #[source_code] String,
+        usize,
+        #[label("this went over the limit")] SourceSpan,
+    ),
+
+    #[error("More than {1} bytes of strings")]
+    #[diagnostic(
+        code(roth::memory_limit_exceeded),
+        help("the program was stopped by `--max-memory`, which counts every string on the stack and in boxes")
+    )]
+    MemoryLimitExceeded(
+        #[source_code] String,
+        usize,
+        #[label("this went over the limit")] SourceSpan,
+    ),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum CliError {
+    #[error("Unknown lint `{0}`")]
+    #[diagnostic(
+        code(roth::unknown_lint),
+        help("the lints are unused-box, unused-macro, unreachable-code, constant-condition, dup-drop, leftover-values and warnings (for all of them)")
+    )]
+    UnknownLint(String),
+
+    #[error("`roth {0}` needs a program")]
+    #[diagnostic(
+        code(roth::missing_input),
+        help("pass a file, `-` to read it from stdin, or `-e '<code>'`")
+    )]
+    MissingInput(String),
+
+    #[error("`roth {0}` needs the name of a macro or box")]
+    #[diagnostic(
+        code(roth::missing_name),
+        help("usage: roth {0} <name> [<file> | - | -e '<code>']")
+    )]
+    MissingName(String),
+
+    #[error("Couldn't find a macro or box called `{0}`")]
+    #[diagnostic(code(roth::unknown_symbol), help("{1}"))]
+    UnknownSymbol(String, String),
+
+    #[error("`{0}` needs a number, not `{1}`")]
+    #[diagnostic(
+        code(roth::invalid_limit),
+        help("i.e. `--max-steps=100000`, `--max-time=500` (milliseconds), `--max-stack=1000` or `--max-memory=65536` (bytes)")
+    )]
+    InvalidLimit(String, String),
+
+    #[error("`{0}` isn't an I/O word")]
+    #[diagnostic(code(roth::unknown_io_word), help("`--allow-io` takes one of {1}"))]
+    UnknownIoWord(String, String),
+
+    #[error("`-I` needs a directory")]
+    #[diagnostic(
+        code(roth::missing_include_dir),
+        help("i.e. `-I lib` to look for includes in `lib` too")
+    )]
+    MissingIncludeDir,
+
+    #[error("Unexpected arguments `{0}`")]
+    #[diagnostic(
+        code(roth::unexpected_arguments),
+        help("usage: roth [-I <dir>] [run | check | repl | fmt | expand | tokens | ops | test | debug | lsp | def <name> | refs <name>] [<file> | - | -e '<code>']")
+    )]
+    UnexpectedArguments(String),
+
+    #[error("`{0}` isn't formatted")]
+    #[diagnostic(code(roth::would_reformat), help("run `roth fmt` on it to format it"))]
+    WouldReformat(String),
+
+    #[error("{0} test(s) failed")]
+    #[diagnostic(code(roth::tests_failed))]
+    TestsFailed(usize),
+
+    #[error("Cannot write file")]
+    #[diagnostic(
+        code(roth::cannot_write_file),
+        help("Make sure you have write permissions for `{0}`")
+    )]
+    CannotWriteFile(String),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ReplError {
+    #[error("Unknown command `:{0}`")]
+    #[diagnostic(code(roth::unknown_command), help("type `:help` to see every command"))]
+    UnknownCommand(String),
+
+    #[error("`:{0}` needs an argument")]
+    #[diagnostic(code(roth::missing_argument), help("usage: `{1}`"))]
+    MissingArgument(String, &'static str),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum CheckError {
+    #[error("Stack underflow")]
+    #[diagnostic(
+        code(roth::stack_underflow),
+        help("`{1}` needs {2} value(s) on the stack, but at this point there will only be {3}")
+    )]
+    StackUnderflow(
+        #[source_code] String,
+        String, // op
+        usize,  // needed
+        usize,  // available
+        #[label("not enough values on the stack for this")] SourceSpan,
+    ),
+
+    #[error("Unbalanced if statement")]
+    #[diagnostic(
+        code(roth::unbalanced_if),
+        help("the body of an `if` runs only sometimes, so it has to leave the stack as it found it ({1} before, {2} after)")
+    )]
+    UnbalancedIf(
+        #[source_code] String,
+        String, // stack before the body
+        String, // stack after the body
+        #[label("this if statement")] SourceSpan,
+        #[label("changes the stack by the time it gets here")] SourceSpan,
+    ),
+
+    #[error("Unbalanced loop")]
+    #[diagnostic(
+        code(roth::unbalanced_until),
+        help("the body of an `until` has to leave the stack as it found it, plus a bool for the next check ({1} before, {2} after)")
+    )]
+    UnbalancedUntil(
+        #[source_code] String,
+        String, // stack before the body
+        String, // stack after the body
+        #[label("this loop")] SourceSpan,
+        #[label("doesn't leave the expected stack by the time it gets here")] SourceSpan,
+    ),
+
+    #[error("Macro doesn't match its stack effect")]
+    #[diagnostic(
+        code(roth::stack_effect_mismatch),
+        help("the body leaves {2} on the stack, but the stack effect says it leaves {1}")
+    )]
+    StackEffectMismatch(
+        #[source_code] String,
+        String, // declared outputs
+        String, // actual outputs
+        #[label("declared here")] SourceSpan,
+        #[label("by the end of the body the stack is different")] SourceSpan,
+    ),
+
+    #[error("Macro takes more values than its stack effect says")]
+    #[diagnostic(
+        code(roth::stack_effect_underflow),
+        help("`{1}` needs {2} value(s), but only {3} are declared in the stack effect")
+    )]
+    StackEffectUnderflow(
+        #[source_code] String,
+        String, // op
+        usize,  // needed
+        usize,  // available
+        #[label("declared here")] SourceSpan,
+        #[label("needs more values than that")] SourceSpan,
+    ),
+
+    #[error("Wrong type passed to macro")]
+    #[diagnostic(
+        code(roth::argument_type_mismatch),
+        help("the stack effect of `{1}` says this should be {2}")
+    )]
+    ArgumentTypeMismatch(
+        #[source_code] String,
+        String,  // macro name
+        ValType, // expected type
+        #[label("this value")] SourceSpan,
+        #[label("is passed to this")] SourceSpan,
+    ),
+
+    #[error("Unclosed block")]
+    #[diagnostic(
+        code(roth::unclosed_block),
+        help("every `if` and `until` needs a corresponding `end`")
+    )]
+    UnclosedBlock(
         #[source_code] String,
-        #[label("no box with this name")] SourceSpan
-    )
+        #[label("this has no closing 'end' token")] SourceSpan,
+    ),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum LintWarning {
+    #[error("Unused box")]
+    #[diagnostic(
+        code(roth::unused_box),
+        severity(Warning),
+        help("`{1}` is never used, so it can be removed")
+    )]
+    UnusedBox(
+        #[source_code] String,
+        String, // box name
+        #[label("created here")] SourceSpan,
+    ),
+
+    #[error("Unused macro")]
+    #[diagnostic(
+        code(roth::unused_macro),
+        severity(Warning),
+        help("`{1}` is never expanded, so it can be removed")
+    )]
+    UnusedMacro(
+        #[source_code] String,
+        String, // macro name
+        #[label("defined here")] SourceSpan,
+    ),
+
+    #[error("Unreachable code")]
+    #[diagnostic(
+        code(roth::unreachable_code),
+        severity(Warning),
+        help("the condition never lets this block run, so it can be removed")
+    )]
+    UnreachableCode(
+        #[source_code] String,
+        #[label("this condition")] SourceSpan,
+        #[label("means this never runs")] SourceSpan,
+    ),
+
+    #[error("Constant condition")]
+    #[diagnostic(
+        code(roth::constant_condition),
+        severity(Warning),
+        help("this `if` always runs, so the condition and its `end` can be removed")
+    )]
+    ConstantCondition(
+        #[source_code] String,
+        #[label("this is always true")] SourceSpan,
+    ),
+
+    #[error("`dup drop` does nothing")]
+    #[diagnostic(
+        code(roth::dup_drop),
+        severity(Warning),
+        help("dropping a value right after duplicating it leaves the stack as it was")
+    )]
+    DupDrop(
+        #[source_code] String,
+        #[label("these cancel out")] SourceSpan,
+    ),
+
+    #[error("Values left on the stack")]
+    #[diagnostic(
+        code(roth::leftover_values),
+        severity(Warning),
+        help("{1} value(s) are still on the stack when the program ends")
+    )]
+    LeftoverValues(
+        #[source_code] String,
+        usize,
+        #[label("this one is on top")] SourceSpan,
+    ),
 }
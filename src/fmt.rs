@@ -0,0 +1,126 @@
+use miette::Result;
+
+use crate::{
+    lexer::Lexer,
+    token::{Token, TokenKind},
+};
+
+static INDENT: &str = "  ";
+
+// One space between words, lines kept where they were (but never more than one
+// blank line in a row), and `if`/`until`/`macro` bodies indented. Comments are kept.
+pub fn format(source: &str) -> Result<String> {
+    let tokens = Lexer::new(source).with_comments().lex()?;
+
+    // spans count chars, so this is the line every char is on
+    let lines: Vec<usize> = source
+        .chars()
+        .scan(0, |line, c| {
+            let this = *line;
+            if c == '\n' {
+                *line += 1;
+            }
+            Some(this)
+        })
+        .collect();
+
+    let mut out = String::with_capacity(source.len());
+    let mut depth: usize = 0;
+    // the line the previous token ended on
+    let mut last_line = None;
+    for token in &tokens {
+        let text = text_of(token);
+        let start = token.span.offset();
+        let first_line = lines[start];
+
+        match last_line {
+            Some(last) if last == first_line => out.push(' '),
+            Some(last) => {
+                out.push('\n');
+                if first_line > last + 1 {
+                    out.push('\n');
+                }
+                indent(&mut out, depth, token.kind);
+            }
+            None => indent(&mut out, depth, token.kind),
+        }
+        out.push_str(&text);
+
+        match token.kind {
+            TokenKind::If | TokenKind::Until | TokenKind::Macro | TokenKind::Test => depth += 1,
+            TokenKind::End => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        // strings can go over more than one line
+        last_line = Some(lines[start + text.chars().count() - 1]);
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+// `end` lines up with whatever opened its block
+fn indent(out: &mut String, depth: usize, kind: TokenKind) {
+    let level = match kind {
+        TokenKind::End => depth.saturating_sub(1),
+        _ => depth,
+    };
+    out.push_str(&INDENT.repeat(level));
+}
+
+// The lexer takes the quotes off of strings
+fn text_of(token: &Token) -> String {
+    match token.kind {
+        TokenKind::String => format!("\"{}\"", token.inner),
+        _ => token.inner.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::format;
+    use expect_test::expect;
+
+    #[test]
+    fn test_format() {
+        let source = "  // count to 5
+box   type::int sum
+
+
+false until // forever
+sum unpack 1 +    sum pack
+    \"sum is  \" sum unpack + print
+sum unpack 5 eq
+   end
+
+macro sq ( int -- int )
+dup *
+end
+3 sq print   ";
+
+        let actual = format(source).unwrap();
+
+        let expected = expect![[r#"
+            // count to 5
+            box type::int sum
+
+            false until // forever
+              sum unpack 1 + sum pack
+              "sum is  " sum unpack + print
+              sum unpack 5 eq
+            end
+
+            macro sq ( int -- int )
+              dup *
+            end
+            3 sq print
+        "#]];
+
+        expected.assert_eq(&actual);
+        // formatting twice shouldn't change anything
+        assert_eq!(format(&actual).unwrap(), actual);
+    }
+}
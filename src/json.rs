@@ -0,0 +1,251 @@
+use std::path::Path;
+
+use miette::{Diagnostic, Report, Severity, SourceCode, SourceSpan};
+use serde_json::{json, Value};
+
+use crate::{error::Located, preprocessor::LineMap};
+
+// `--message-format=json`: one object per line for every diagnostic in `report`,
+// so editors and CI don't have to scrape the fancy output
+pub fn print_report(report: &Report, file: &str) {
+    for value in report_diagnostics(report, file) {
+        println!("{}", value);
+    }
+}
+
+fn report_diagnostics(report: &Report, file: &str) -> Vec<Value> {
+    match report.downcast_ref::<Located>() {
+        Some(located) => diagnostics(located.error.as_ref(), file, Some(&located.lines)),
+        None => diagnostics(report.as_ref(), file, None),
+    }
+}
+
+// Labels on the expanded program are reported against the file and place they
+// were written when there are `lines` to say where that is
+fn diagnostics(diagnostic: &dyn Diagnostic, file: &str, lines: Option<&LineMap>) -> Vec<Value> {
+    // a batch of errors (see `Errors`) has no source of its own, and is reported as
+    // each of its errors
+    if diagnostic.source_code().is_none() {
+        if let Some(related) = diagnostic.related() {
+            return related
+                .flat_map(|related| diagnostics(related, file, lines))
+                .collect();
+        }
+    }
+
+    // anything else related to an error (like a backtrace) stays with it
+    let related: Vec<Value> = diagnostic
+        .related()
+        .map(|related| {
+            related
+                .flat_map(|related| diagnostics(related, file, lines))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let labels: Vec<Value> = match (diagnostic.source_code(), diagnostic.labels()) {
+        (Some(source), Some(labels)) => labels
+            .map(|label| {
+                let span = label.inner();
+                let (label_file, start, end) = locate(source, lines, span);
+                // included files are named from next to the program
+                let label_file = match (label_file, Path::new(file).parent()) {
+                    (Some(included), Some(dir)) => dir.join(included).display().to_string(),
+                    (Some(included), None) => included.to_string(),
+                    (None, _) => file.to_string(),
+                };
+
+                json!({
+                    "label": label.label(),
+                    "file": label_file,
+                    "byte_start": start.map(|(byte, _, _)| byte),
+                    "byte_end": end.map(|(byte, _, _)| byte),
+                    "line_start": start.map(|(_, line, _)| line),
+                    "column_start": start.map(|(_, _, column)| column),
+                    "line_end": end.map(|(_, line, _)| line),
+                    "column_end": end.map(|(_, _, column)| column),
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let severity = match diagnostic.severity() {
+        Some(Severity::Warning) => "warning",
+        Some(Severity::Advice) => "advice",
+        _ => "error",
+    };
+    // where the first label is
+    let file = labels
+        .first()
+        .and_then(|label| label["file"].as_str())
+        .unwrap_or(file);
+
+    vec![json!({
+        "code": diagnostic.code().map(|code| code.to_string()),
+        "severity": severity,
+        "message": diagnostic.to_string(),
+        "help": diagnostic.help().map(|help| help.to_string()),
+        "file": file,
+        "labels": labels,
+        "related": related,
+    })]
+}
+
+// (byte, line, column)
+type Location = Option<(usize, usize, usize)>;
+
+// The file `span` is in (`None` for the program), and where it starts and ends
+// in there
+fn locate<'a>(
+    source: &dyn SourceCode,
+    lines: Option<&'a LineMap>,
+    span: &SourceSpan,
+) -> (Option<&'a str>, Location, Location) {
+    let end = span.offset() + span.len();
+    let lines = match lines {
+        Some(lines) => lines,
+        None => {
+            let at = |offset: usize| {
+                position(source, offset).map(|(line, column)| (offset, line, column))
+            };
+            return (None, at(span.offset()), at(end));
+        }
+    };
+
+    let at = |offset: usize| {
+        let (file, byte) = lines.offset_of(offset)?;
+        let (_, line, column) = lines.position_of(offset)?;
+        Some((file, (byte, line, column)))
+    };
+    match (at(span.offset()), at(end)) {
+        (Some((file, start)), end) => (file, Some(start), end.map(|(_, end)| end)),
+        _ => (None, None, None),
+    }
+}
+
+// 1-based (line, column) of a byte offset
+pub fn position(source: &dyn SourceCode, offset: usize) -> Option<(usize, usize)> {
+    let span: SourceSpan = (offset, 0).into();
+    source
+        .read_span(&span, 0, 0)
+        .ok()
+        .map(|contents| (contents.line() + 1, contents.column() + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        error::{Located, RuntimeError},
+        json::{diagnostics, report_diagnostics},
+        lexer::Lexer,
+        parser::Parser,
+        preprocessor::PreProcessor,
+        runtime::Runtime,
+    };
+    use expect_test::expect;
+    use miette::Report;
+
+    #[test]
+    fn test_runtime_error() {
+        let report: Report =
+            RuntimeError::EmptyStackError(String::from("1 2 +\n+ print"), 2, 1, (6, 1).into())
+                .into();
+
+        let actual = diagnostics(report.as_ref(), "e.roth", None);
+
+        let expected = expect![[r#"
+            [
+              {
+                "code": "roth::empty_stack",
+                "file": "e.roth",
+                "help": "this needs 2 value(s), but there were only 1 on the stack",
+                "labels": [
+                  {
+                    "byte_end": 7,
+                    "byte_start": 6,
+                    "column_end": 2,
+                    "column_start": 1,
+                    "file": "e.roth",
+                    "label": "not enough values for this",
+                    "line_end": 2,
+                    "line_start": 2
+                  }
+                ],
+                "message": "Empty stack",
+                "related": [],
+                "severity": "error"
+              }
+            ]"#]];
+
+        expected.assert_eq(&serde_json::to_string_pretty(&actual).unwrap());
+    }
+
+    #[test]
+    fn test_written_locations() {
+        let dir = std::env::temp_dir().join(format!("roth-json-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/util.roth"), "// util\nmacro add2 2 + + end\n").unwrap();
+        fs::write(dir.join("lib/bad.roth"), "// bad\n\n  drop drop drop\n").unwrap();
+
+        let mut actual = Vec::new();
+        // a macro used in the program, and a file that goes wrong on its own
+        for source in [
+            "include(\"lib/util.roth\")\n\n1 add2",
+            "1 2\ninclude(\"lib/bad.roth\")\ndrop",
+        ] {
+            let path = dir.join("main.roth");
+            fs::write(&path, source).unwrap();
+            let mut preprocessor = PreProcessor::new(source).with_path(&path);
+            let expanded = preprocessor.expand().unwrap();
+            let tokens = Lexer::new(&expanded).lex().unwrap();
+            let ops = Parser::new(tokens, expanded.clone())
+                .with_inclusions(preprocessor.inclusions().to_vec())
+                .with_expansions(preprocessor.expansions().to_vec())
+                .parse()
+                .unwrap();
+            let report = Runtime::new(expanded, ops)
+                .with_expansions(preprocessor.expansions().to_vec())
+                .with_inclusions(preprocessor.inclusions().to_vec())
+                .run()
+                .map_err(Located::wrap(preprocessor.lines()))
+                .unwrap_err();
+
+            for value in report_diagnostics(&report, "app/main.roth") {
+                let related = value["related"].as_array().unwrap().iter();
+                for diagnostic in std::iter::once(&value).chain(related) {
+                    actual.push(format!(
+                        "{} in {}",
+                        diagnostic["message"], diagnostic["file"]
+                    ));
+                    for label in diagnostic["labels"].as_array().unwrap() {
+                        actual.push(format!(
+                            "  {} {}:{}:{} bytes {}..{}",
+                            label["label"],
+                            label["file"],
+                            label["line_start"],
+                            label["column_start"],
+                            label["byte_start"],
+                            label["byte_end"],
+                        ));
+                    }
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        let expected = expect![[r#"
+            "Empty stack" in "app/main.roth"
+              "not enough values for this" "app/main.roth":3:3 bytes 28..32
+            "in macro `add2`" in "app/main.roth"
+              "`add2` was expanded here" "app/main.roth":3:3 bytes 28..32
+              "defined here" "app/lib/util.roth":2:7 bytes 14..18
+            "Empty stack" in "app/lib/bad.roth"
+              "not enough values for this" "app/lib/bad.roth":3:13 bytes 20..24
+            "in `lib/bad.roth`, line 3" in "app/lib/bad.roth"
+              "this came from `lib/bad.roth`" "app/lib/bad.roth":3:13 bytes 20..24"#]];
+        expected.assert_eq(&actual.join("\n"));
+    }
+}
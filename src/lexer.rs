@@ -1,25 +1,94 @@
 use crate::{
-    error::ParseError,
+    error::{Errors, ParseError},
+    suggest::did_you_mean,
     token::{Token, TokenKind},
 };
-use miette::Result;
+use miette::{Report, Result};
 use std::collections::VecDeque;
 
+// Every word with a fixed meaning, checked before falling back to literals and idents
+pub static KEYWORDS: &[(&str, TokenKind)] = &[
+    ("+", TokenKind::Add),
+    ("-", TokenKind::Sub),
+    ("*", TokenKind::Mul),
+    ("/", TokenKind::Div),
+    ("print", TokenKind::Print),
+    ("or", TokenKind::Or),
+    ("and", TokenKind::And),
+    ("not", TokenKind::Not),
+    ("eq", TokenKind::Eq),
+    ("<", TokenKind::LessThan),
+    (">", TokenKind::GreaterThan),
+    ("<=", TokenKind::LessThanEq),
+    (">=", TokenKind::GreaterThanEq),
+    ("if", TokenKind::If),
+    ("end", TokenKind::End),
+    ("macro", TokenKind::Macro),
+    ("test", TokenKind::Test),
+    ("module", TokenKind::Module),
+    ("export", TokenKind::Export),
+    ("dup", TokenKind::Dup),
+    ("drop", TokenKind::Drop),
+    ("swap", TokenKind::Swap),
+    ("over", TokenKind::Over),
+    ("rot", TokenKind::Rot),
+    ("type", TokenKind::Type),
+    ("type::int", TokenKind::TypeInt),
+    ("type::str", TokenKind::TypeStr),
+    ("type::bool", TokenKind::TypeBool),
+    ("type::box<int>", TokenKind::TypeBoxedInt),
+    ("type::box<str>", TokenKind::TypeBoxedStr),
+    ("type::box<bool>", TokenKind::TypeBoxedBool),
+    ("assert", TokenKind::Assert),
+    ("assert-eq", TokenKind::AssertEq),
+    ("assert-ne", TokenKind::AssertNe),
+    ("assert-type", TokenKind::AssertType),
+    ("assert-stack-depth", TokenKind::AssertStackDepth),
+    ("breakpoint", TokenKind::Breakpoint),
+    ("box", TokenKind::Box),
+    ("pack", TokenKind::Pack),
+    ("unpack", TokenKind::Unpack),
+    ("until", TokenKind::Until),
+];
+
 pub struct Lexer {
+    buffer: String,
     source: VecDeque<char>,
     tokens: Vec<Token>,
     cursor: usize,
+    offset: usize,
+    comments: bool,
+    errors: Vec<Report>,
 }
 
 impl Lexer {
     pub fn new(buffer: &str) -> Self {
         Self {
+            buffer: buffer.to_string(),
             source: buffer.chars().collect(),
             tokens: vec![],
             cursor: 0,
+            offset: 0,
+            comments: false,
+            errors: vec![],
         }
     }
 
+    // Start counting spans from `offset` instead of 0, for when this buffer
+    // gets appended to some bigger source (i.e. a repl session)
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.cursor = offset;
+        self.offset = offset;
+        self
+    }
+
+    // Keep comments as `TokenKind::Comment` tokens instead of throwing them away,
+    // for the formatter
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
     fn eat_trivia(&mut self) {
         let trivia: Vec<char> = vec![' ', '\n', '\t'];
         while let Some(c) = self.source.front() {
@@ -33,50 +102,32 @@ impl Lexer {
     }
 
     fn create_token(&self, mut raw_token: String, start: usize) -> Result<Token, ParseError> {
-        let kind = match raw_token.as_str() {
-            "+" => Ok(TokenKind::Add),
-            "-" => Ok(TokenKind::Sub),
-            "*" => Ok(TokenKind::Mul),
-            "/" => Ok(TokenKind::Div),
-            "print" => Ok(TokenKind::Print),
-            "or" => Ok(TokenKind::Or),
-            "and" => Ok(TokenKind::And),
-            "not" => Ok(TokenKind::Not),
-            "eq" => Ok(TokenKind::Eq),
-            "<" => Ok(TokenKind::LessThan),
-            ">" => Ok(TokenKind::GreaterThan),
-            "<=" => Ok(TokenKind::LessThanEq),
-            ">=" => Ok(TokenKind::GreaterThanEq),
-            "if" => Ok(TokenKind::If),
-            "end" => Ok(TokenKind::End),
-            "macro" => Ok(TokenKind::Macro),
-            "dup" => Ok(TokenKind::Dup),
-            "drop" => Ok(TokenKind::Drop),
-            "swap" => Ok(TokenKind::Swap),
-            "over" => Ok(TokenKind::Over),
-            "rot" => Ok(TokenKind::Rot),
-            "type" => Ok(TokenKind::Type),
-            "type::int" => Ok(TokenKind::TypeInt),
-            "type::str" => Ok(TokenKind::TypeStr),
-            "type::bool" => Ok(TokenKind::TypeBool),
-            "type::box<int>" => Ok(TokenKind::TypeBoxedInt),
-            "type::box<str>" => Ok(TokenKind::TypeBoxedStr),
-            "type::box<bool>" => Ok(TokenKind::TypeBoxedBool),
-            "assert" => Ok(TokenKind::Assert),
-            "box" => Ok(TokenKind::Box),
-            "pack" => Ok(TokenKind::Pack),
-            "unpack" => Ok(TokenKind::Unpack),
-            "until" => Ok(TokenKind::Until),
-            _ => {
+        let keyword = KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == raw_token)
+            .map(|(_, kind)| *kind);
+
+        let kind = match keyword {
+            Some(kind) => Ok(kind),
+            None => {
                 if raw_token.starts_with('\"') {
-                    if raw_token.ends_with('\"') {
+                    if raw_token.len() > 1 && raw_token.ends_with('\"') {
                         // remove leading and trailing quotation marks
                         raw_token.remove(0);
                         raw_token.remove(raw_token.len() - 1);
                         Ok(TokenKind::String)
                     } else {
-                        Err(ParseError::UnterminatedStringLiteral)
+                        // errors only have `buffer` to show, so they can't use the offset
+                        Err(ParseError::UnterminatedStringLiteral(
+                            self.buffer.clone(),
+                            (start - self.offset, raw_token.chars().count()).into(),
+                        ))
                     }
+                } else if raw_token.starts_with("import(") && raw_token.ends_with(')') {
+                    Ok(TokenKind::Import)
+                } else if raw_token.starts_with("type::") {
+                    // otherwise a typo in a type would end up as a box name
+                    Err(self.unknown_type(&raw_token, start))
                 } else if raw_token.parse::<i128>().is_ok() {
                     Ok(TokenKind::Number)
                 } else if raw_token.parse::<bool>().is_ok() {
@@ -90,17 +141,49 @@ impl Lexer {
         Ok(Token::new(raw_token, start, kind))
     }
 
-    fn eat_until_newline(&mut self) {
+    fn unknown_type(&self, raw_token: &str, start: usize) -> ParseError {
+        let types: Vec<&str> = KEYWORDS
+            .iter()
+            .map(|(keyword, _)| *keyword)
+            .filter(|keyword| keyword.starts_with("type::"))
+            .collect();
+        let help = match did_you_mean(raw_token, &types) {
+            Some(suggestion) => format!("did you mean `{}`?", suggestion),
+            None => format!("the types are {}", types.join(", ")),
+        };
+
+        ParseError::UnknownType(
+            self.buffer.clone(),
+            help,
+            (start - self.offset, raw_token.chars().count()).into(),
+        )
+    }
+
+    // Returns what was eaten, without the newline
+    fn eat_until_newline(&mut self) -> String {
+        let mut eaten = String::new();
         while let Some(c) = self.source.pop_front() {
             self.cursor += 1;
             if c == '\n' {
                 self.eat_trivia();
                 break;
             }
+            eaten.push(c);
         }
+
+        eaten
+    }
+
+    pub fn lex(self) -> Result<Vec<Token>> {
+        let (tokens, errors) = self.lex_recovering();
+        Errors::into_result(errors)?;
+
+        Ok(tokens)
     }
 
-    pub fn lex(mut self) -> Result<Vec<Token>> {
+    // Bad tokens are left out instead of stopping everything, for when some tokens
+    // are better than none (i.e. highlighting a file that's being edited)
+    pub fn lex_recovering(mut self) -> (Vec<Token>, Vec<Report>) {
         self.eat_trivia();
         while self.source.front().is_some() {
             let mut start = self.cursor;
@@ -112,8 +195,7 @@ impl Lexer {
 
                 // make sure we aren't at a whitespace inside of a string
                 if c.is_whitespace() && !in_mid_of_string {
-                    let token = self.create_token(curr.clone(), start)?;
-                    self.tokens.push(token);
+                    self.push_token(curr.clone(), start);
                     curr.clear();
                     break;
                 } else {
@@ -122,7 +204,12 @@ impl Lexer {
 
                     // if we are a comment, eat until new line
                     if curr == "//" {
-                        self.eat_until_newline();
+                        let comment = self.eat_until_newline();
+                        if self.comments {
+                            let text = format!("//{}", comment.trim_end());
+                            self.tokens
+                                .push(Token::new(text, start, TokenKind::Comment));
+                        }
                         curr.clear();
                         start = self.cursor;
                     }
@@ -132,15 +219,22 @@ impl Lexer {
             // Check if we have an unfinished token here
             // which can happen at the end of the file
             if !curr.is_empty() {
-                let token = self.create_token(curr.clone(), start)?;
-                self.tokens.push(token);
+                self.push_token(curr.clone(), start);
                 curr.clear();
             }
 
             self.eat_trivia();
         }
 
-        Ok(self.tokens)
+        (self.tokens, self.errors)
+    }
+
+    // Bad tokens are left out, so we can keep going and find the rest of the errors
+    fn push_token(&mut self, raw_token: String, start: usize) {
+        match self.create_token(raw_token, start) {
+            Ok(token) => self.tokens.push(token),
+            Err(e) => self.errors.push(e.into()),
+        }
     }
 }
 
@@ -367,4 +461,32 @@ mod tests {
 
         expected.assert_debug_eq(&actual);
     }
+
+    #[test]
+    fn test_unterminated_string() {
+        let actual = Lexer::new("1 \"abc print")
+            .with_offset(10)
+            .lex()
+            .unwrap_err();
+
+        let expected = expect![[r#"
+            [
+                LabeledSpan {
+                    label: Some(
+                        "this string is never closed",
+                    ),
+                    span: SourceSpan {
+                        offset: SourceOffset(
+                            2,
+                        ),
+                        length: SourceOffset(
+                            10,
+                        ),
+                    },
+                },
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual.labels().unwrap().collect::<Vec<_>>());
+    }
 }
@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+use miette::SourceSpan;
+
+use crate::{error::RuntimeError, stack::Stack};
+
+// Caps on what a program can use, for running code we don't trust. `None` means
+// no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    // ops run, counting every time a loop runs them again
+    pub steps: Option<u64>,
+    pub time: Option<Duration>,
+    // values on the stack
+    pub stack_depth: Option<usize>,
+    // bytes in strings, on the stack and in boxes
+    pub memory: Option<usize>,
+}
+
+// What a program has used so far, against its limits
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    // when the first op ran
+    started: Option<Instant>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    // Before running the op at `span`
+    pub fn step(&mut self, source: &str, span: &SourceSpan) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(steps) = self.limits.steps {
+            if self.steps > steps {
+                return Err(RuntimeError::StepLimitExceeded(
+                    source.to_string(),
+                    steps,
+                    span.clone(),
+                ));
+            }
+        }
+
+        let started = *self.started.get_or_insert_with(Instant::now);
+        if let Some(time) = self.limits.time {
+            if started.elapsed() > time {
+                return Err(RuntimeError::TimeLimitExceeded(
+                    source.to_string(),
+                    format!("{:?}", time),
+                    span.clone(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // After running the op at `span`
+    pub fn check(
+        &self,
+        source: &str,
+        span: &SourceSpan,
+        stack: &Stack,
+        box_bytes: usize,
+    ) -> Result<(), RuntimeError> {
+        if let Some(depth) = self.limits.stack_depth {
+            if stack.len() > depth {
+                return Err(RuntimeError::StackLimitExceeded(
+                    source.to_string(),
+                    depth,
+                    span.clone(),
+                ));
+            }
+        }
+
+        if let Some(memory) = self.limits.memory {
+            if stack.bytes() + box_bytes > memory {
+                return Err(RuntimeError::MemoryLimitExceeded(
+                    source.to_string(),
+                    memory,
+                    span.clone(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{lexer::Lexer, limits::Limits, parser::Parser, runtime::Runtime};
+    use expect_test::expect;
+
+    #[test]
+    fn test_limits() {
+        let forever = "false until 1 drop false end";
+        let growing = "false until 1 false end";
+        let strings = "box type::str s \"ab\" s pack false until s unpack dup + s pack false end";
+        let cases = [
+            (
+                forever,
+                Limits {
+                    steps: Some(1000),
+                    ..Limits::default()
+                },
+            ),
+            (
+                forever,
+                Limits {
+                    time: Some(Duration::from_millis(10)),
+                    ..Limits::default()
+                },
+            ),
+            (
+                growing,
+                Limits {
+                    stack_depth: Some(50),
+                    ..Limits::default()
+                },
+            ),
+            (
+                strings,
+                Limits {
+                    memory: Some(100),
+                    ..Limits::default()
+                },
+            ),
+            // plenty of room
+            (
+                "1 2 + drop",
+                Limits {
+                    steps: Some(4),
+                    stack_depth: Some(2),
+                    ..Limits::default()
+                },
+            ),
+        ];
+
+        let actual: Vec<String> = cases
+            .into_iter()
+            .map(|(source, limits)| {
+                let tokens = Lexer::new(source).lex().unwrap();
+                let ops = Parser::new(tokens, source.to_string()).parse().unwrap();
+                match Runtime::new(source.to_string(), ops)
+                    .with_limits(limits)
+                    .run()
+                {
+                    Ok(_) => String::from("ok"),
+                    Err(e) => format!("{}: {}", e.code().unwrap(), e),
+                }
+            })
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "roth::step_limit_exceeded: Ran more than 1000 ops",
+                "roth::time_limit_exceeded: Ran for more than 10ms",
+                "roth::stack_limit_exceeded: More than 50 values on the stack",
+                "roth::memory_limit_exceeded: More than 100 bytes of strings",
+                "ok",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
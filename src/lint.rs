@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use miette::{Report, SourceSpan};
+
+use crate::{
+    error::{Denied, LintWarning},
+    op::{Op, OpKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedBox,
+    UnusedMacro,
+    UnreachableCode,
+    ConstantCondition,
+    DupDrop,
+    LeftoverValues,
+}
+
+// Names used by `--allow=...`, `--warn=...` and `--deny=...`
+pub static LINTS: &[(&str, Lint)] = &[
+    ("unused-box", Lint::UnusedBox),
+    ("unused-macro", Lint::UnusedMacro),
+    ("unreachable-code", Lint::UnreachableCode),
+    ("constant-condition", Lint::ConstantCondition),
+    ("dup-drop", Lint::DupDrop),
+    ("leftover-values", Lint::LeftoverValues),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// Looks for code that is valid but probably not what was meant
+pub struct Linter {
+    source: String,
+    ops: Vec<Op>,
+    unused_macros: Vec<(String, SourceSpan)>,
+    leftovers: Vec<SourceSpan>,
+    levels: HashMap<Lint, Level>,
+}
+
+impl Linter {
+    pub fn new(source: String, ops: &VecDeque<Op>) -> Self {
+        Self {
+            source,
+            ops: ops.iter().cloned().collect(),
+            unused_macros: Vec::new(),
+            leftovers: Vec::new(),
+            levels: HashMap::new(),
+        }
+    }
+
+    // Macros are gone by the time we have ops, so the preprocessor tells us about them
+    pub fn with_unused_macros(mut self, unused_macros: Vec<(String, SourceSpan)>) -> Self {
+        self.unused_macros = unused_macros;
+        self
+    }
+
+    // Where the values still on the stack at the end came from (see `Checker::leftovers`)
+    pub fn with_leftovers(mut self, leftovers: Vec<SourceSpan>) -> Self {
+        self.leftovers = leftovers;
+        self
+    }
+
+    // Every lint warns unless told otherwise
+    pub fn with_level(mut self, lint: Lint, level: Level) -> Self {
+        self.levels.insert(lint, level);
+        self
+    }
+
+    // (warnings, denied lints)
+    pub fn lint(&self) -> (Vec<Report>, Vec<Report>) {
+        let mut warnings = Vec::new();
+        let mut denied = Vec::new();
+
+        for (lint, warning) in self.find() {
+            match self.levels.get(&lint).copied().unwrap_or(Level::Warn) {
+                Level::Allow => {}
+                Level::Warn => warnings.push(warning.into()),
+                Level::Deny => denied.push(Denied(warning.into()).into()),
+            }
+        }
+
+        (warnings, denied)
+    }
+
+    fn find(&self) -> Vec<(Lint, LintWarning)> {
+        let mut found = Vec::new();
+
+        // unused boxes
+        let used: HashSet<&String> = self
+            .ops
+            .iter()
+            .filter_map(|op| match &op.kind {
+                OpKind::PushBox { name } => Some(name),
+                _ => None,
+            })
+            .collect();
+        for op in &self.ops {
+            if let OpKind::CreateBox { name, .. } = &op.kind {
+                if !used.contains(name) {
+                    found.push((
+                        Lint::UnusedBox,
+                        LintWarning::UnusedBox(self.source.clone(), name.clone(), op.span.clone()),
+                    ));
+                }
+            }
+        }
+
+        for (name, span) in &self.unused_macros {
+            found.push((
+                Lint::UnusedMacro,
+                LintWarning::UnusedMacro(self.source.clone(), name.clone(), span.clone()),
+            ));
+        }
+
+        for (idx, pair) in self.ops.windows(2).enumerate() {
+            match (&pair[0].kind, &pair[1].kind) {
+                // the body of `false if` or `true until` never runs
+                (OpKind::PushBool { val: false }, OpKind::If)
+                | (OpKind::PushBool { val: true }, OpKind::Until) => {
+                    let body = match self.body_of(idx + 1) {
+                        Some(body) => body,
+                        None => pair[1].span.clone(),
+                    };
+                    found.push((
+                        Lint::UnreachableCode,
+                        LintWarning::UnreachableCode(
+                            self.source.clone(),
+                            merge(&pair[0].span, &pair[1].span),
+                            body,
+                        ),
+                    ));
+                }
+                (OpKind::PushBool { val: true }, OpKind::If) => found.push((
+                    Lint::ConstantCondition,
+                    LintWarning::ConstantCondition(
+                        self.source.clone(),
+                        merge(&pair[0].span, &pair[1].span),
+                    ),
+                )),
+                (OpKind::Dup, OpKind::Drop) => found.push((
+                    Lint::DupDrop,
+                    LintWarning::DupDrop(self.source.clone(), merge(&pair[0].span, &pair[1].span)),
+                )),
+                _ => {}
+            }
+        }
+
+        if let Some(top) = self.leftovers.last() {
+            found.push((
+                Lint::LeftoverValues,
+                LintWarning::LeftoverValues(self.source.clone(), self.leftovers.len(), top.clone()),
+            ));
+        }
+
+        found
+    }
+
+    // Span from the op after the `if`/`until` at `idx` to its corresponding `end`
+    fn body_of(&self, idx: usize) -> Option<SourceSpan> {
+        let mut depth: usize = 0;
+        for op in &self.ops[idx + 1..] {
+            match op.kind {
+                OpKind::If | OpKind::Until => depth += 1,
+                OpKind::End if depth == 0 => {
+                    return Some(merge(&self.ops[idx + 1].span, &op.span));
+                }
+                OpKind::End => depth -= 1,
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+// From the start of `a` to the end of `b`
+fn merge(a: &SourceSpan, b: &SourceSpan) -> SourceSpan {
+    (a.offset(), b.offset() + b.len() - a.offset()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        checker::Checker,
+        lexer::Lexer,
+        lint::{Level, Lint, Linter},
+        parser::Parser,
+    };
+    use expect_test::expect;
+
+    fn test(source: &str) -> Vec<String> {
+        let tokens = Lexer::new(source).lex().unwrap();
+        let ops = Parser::new(tokens, source.to_string()).parse().unwrap();
+        let mut checker = Checker::new(source.to_string(), ops.clone());
+        checker.check();
+
+        let (warnings, denied) = Linter::new(source.to_string(), &ops)
+            .with_leftovers(checker.leftovers())
+            .with_level(Lint::DupDrop, Level::Deny)
+            .with_level(Lint::ConstantCondition, Level::Allow)
+            .lint();
+
+        warnings
+            .iter()
+            .chain(denied.iter())
+            .map(|e| format!("{:?} {}", e.severity().unwrap(), e.code().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lints() {
+        let actual = test(
+            "box type::int unused
+box type::int used 1 used pack
+false if \"never\" print end
+true if 2 print end
+3 dup drop",
+        );
+
+        let expected = expect![[r#"
+            [
+                "Warning roth::unused_box",
+                "Warning roth::unreachable_code",
+                "Warning roth::leftover_values",
+                "Error roth::dup_drop",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
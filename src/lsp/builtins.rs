@@ -0,0 +1,132 @@
+// (word, stack effect, what it does) for everything in `KEYWORDS`, shown on hover
+pub static BUILTINS: &[(&str, &str, &str)] = &[
+    ("+", "( a a -- a )", "adds two ints, or joins two strs"),
+    (
+        "-",
+        "( int int -- int )",
+        "subtracts the top int from the one below it",
+    ),
+    ("*", "( int int -- int )", "multiplies two ints"),
+    (
+        "/",
+        "( int int -- int )",
+        "divides the int below the top by the top int",
+    ),
+    ("print", "( a -- )", "prints the top value"),
+    ("or", "( bool bool -- bool )", "`true` if either bool is"),
+    ("and", "( bool bool -- bool )", "`true` if both bools are"),
+    ("not", "( bool -- bool )", "flips a bool"),
+    (
+        "eq",
+        "( a a -- bool )",
+        "`true` if the two values are equal",
+    ),
+    (
+        "<",
+        "( int int -- bool )",
+        "`true` if the int below the top is smaller",
+    ),
+    (
+        ">",
+        "( int int -- bool )",
+        "`true` if the int below the top is bigger",
+    ),
+    (
+        "<=",
+        "( int int -- bool )",
+        "`true` if the int below the top is smaller or equal",
+    ),
+    (
+        ">=",
+        "( int int -- bool )",
+        "`true` if the int below the top is bigger or equal",
+    ),
+    (
+        "if",
+        "( bool -- )",
+        "runs everything up to its `end` if the bool is `true`",
+    ),
+    (
+        "end",
+        "( -- )",
+        "closes an `if`, `until`, `macro` or `test`",
+    ),
+    (
+        "macro",
+        "( -- )",
+        "`macro name ( effect ) ... end` defines a macro",
+    ),
+    (
+        "test",
+        "( -- )",
+        "`test \"name\" ... end` defines a test for `roth test`",
+    ),
+    (
+        "module",
+        "( -- )",
+        "`module name` at the start of a file lets it be imported with `import(\"file\")`",
+    ),
+    (
+        "export",
+        "( -- )",
+        "`export macro ...` or `export box ...` lets files importing the module use it",
+    ),
+    ("dup", "( a -- a a )", "copies the top value"),
+    ("drop", "( a -- )", "throws away the top value"),
+    ("swap", "( a b -- b a )", "swaps the top two values"),
+    ("over", "( a b -- a b a )", "copies the value below the top"),
+    (
+        "rot",
+        "( a b c -- b c a )",
+        "moves the third value to the top",
+    ),
+    ("type", "( a -- type )", "the type of the top value"),
+    ("type::int", "( -- type )", "the type of ints"),
+    ("type::str", "( -- type )", "the type of strs"),
+    ("type::bool", "( -- type )", "the type of bools"),
+    (
+        "type::box<int>",
+        "( -- type )",
+        "the type of boxes holding an int",
+    ),
+    (
+        "type::box<str>",
+        "( -- type )",
+        "the type of boxes holding a str",
+    ),
+    (
+        "type::box<bool>",
+        "( -- type )",
+        "the type of boxes holding a bool",
+    ),
+    ("assert", "( bool -- )", "fails if the bool is `false`"),
+    (
+        "assert-eq",
+        "( a a -- )",
+        "fails if the two values aren't equal",
+    ),
+    (
+        "assert-ne",
+        "( a a -- )",
+        "fails if the two values are equal",
+    ),
+    (
+        "assert-type",
+        "( a type -- )",
+        "fails if the value isn't of the type",
+    ),
+    (
+        "assert-stack-depth",
+        "( int -- )",
+        "fails if the stack doesn't have that many values",
+    ),
+    ("breakpoint", "( -- )", "stops `roth debug` here"),
+    ("box", "( -- )", "`box type::int name` creates a box"),
+    ("pack", "( a box -- )", "puts a value in a box"),
+    ("unpack", "( box -- a )", "takes the value out of a box"),
+    (
+        "until",
+        "( bool -- )",
+        "repeats everything up to its `end` until the bool left at the end is `true`",
+    ),
+];
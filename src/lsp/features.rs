@@ -0,0 +1,535 @@
+use std::path::{Path, PathBuf};
+
+use miette::{Diagnostic, Severity, SourceSpan};
+use serde_json::{json, Value};
+
+use crate::{
+    analysis::analyze,
+    capabilities::Capabilities,
+    lexer::{Lexer, KEYWORDS},
+    preprocessor::{find_includes, locate_include, search_path, LineMap, PreProcessor},
+    symbols::{Location, Symbol, SymbolIndex},
+    token::{Token, TokenKind},
+};
+
+use super::builtins::BUILTINS;
+
+// Names for the numbers in semantic tokens, in order
+pub static TOKEN_TYPES: &[&str] = &[
+    "keyword", "number", "string", "comment", "type", "macro", "variable", "operator", "function",
+];
+
+// An open file, as the editor has it
+pub struct Document {
+    pub uri: String,
+    pub text: String,
+    // what `roth lsp` was started with, so includes are found and allowed like
+    // `roth check` would
+    pub capabilities: Capabilities,
+    pub include_dirs: Vec<PathBuf>,
+}
+
+impl Document {
+    // for finding includes, `None` if it isn't saved anywhere
+    pub fn dir(&self) -> Option<PathBuf> {
+        uri_to_path(&self.uri)?.parent().map(Path::to_path_buf)
+    }
+
+    // includes are next to the document
+    fn preprocessor(&self) -> PreProcessor {
+        let preprocessor = PreProcessor::new(&self.text)
+            .with_include_dirs(self.include_dirs.clone())
+            .with_capabilities(self.capabilities.clone());
+        match uri_to_path(&self.uri) {
+            Some(path) => preprocessor.with_path(&path),
+            None => preprocessor,
+        }
+    }
+
+    fn tokens(&self) -> Vec<Token> {
+        Lexer::new(&self.text).with_comments().lex_recovering().0
+    }
+
+    // The token under a 0-based (line, character)
+    fn token_at(&self, position: &Value) -> Option<Token> {
+        let offset = offset_at(&self.text, position)?;
+        self.tokens().into_iter().find(|token| {
+            let span = &token.span;
+            span.offset() <= offset && offset <= span.offset() + span.len()
+        })
+    }
+}
+
+// Everything `roth check` would say about the document
+pub fn diagnostics(document: &Document) -> Vec<Value> {
+    let mut found = Vec::new();
+    match analyze(document.preprocessor(), &[]) {
+        // preprocessor errors are about the text as it is
+        Err(report) => {
+            for diagnostic in flatten(report.as_ref()) {
+                found.extend(to_lsp(diagnostic, &document.text, None));
+            }
+        }
+        Ok(analysis) => {
+            for report in analysis.errors.iter().chain(analysis.warnings.iter()) {
+                for diagnostic in flatten(report.as_ref()) {
+                    found.extend(to_lsp(diagnostic, &document.text, Some(&analysis.lines)));
+                }
+            }
+        }
+    }
+
+    found
+}
+
+// A batch of errors (see `Errors`) is reported as each of its errors
+fn flatten(diagnostic: &dyn Diagnostic) -> Vec<&dyn Diagnostic> {
+    match (diagnostic.source_code(), diagnostic.related()) {
+        (None, Some(related)) => related.flat_map(flatten).collect(),
+        _ => vec![diagnostic],
+    }
+}
+
+// Diagnostics about expanded source are put back where they came from with
+// `lines`. Ones from included files are left for those files.
+fn to_lsp(diagnostic: &dyn Diagnostic, text: &str, lines: Option<&LineMap>) -> Option<Value> {
+    let label = diagnostic.labels().and_then(|mut labels| labels.next());
+    let range = match &label {
+        Some(label) => range(text, lines, label.inner())?,
+        None => range(text, None, &(0, 0).into())?,
+    };
+
+    let mut message = diagnostic.to_string();
+    if let Some(text) = label.as_ref().and_then(|label| label.label()) {
+        message.push_str(&format!("\n{}", text));
+    }
+    if let Some(help) = diagnostic.help() {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    let severity = match diagnostic.severity() {
+        Some(Severity::Warning) => 2,
+        Some(Severity::Advice) => 3,
+        _ => 1,
+    };
+
+    Some(json!({
+        "range": range,
+        "severity": severity,
+        "code": diagnostic.code().map(|code| code.to_string()),
+        "source": "roth",
+        "message": message,
+    }))
+}
+
+fn range(text: &str, lines: Option<&LineMap>, span: &SourceSpan) -> Option<Value> {
+    let end = span.offset() + span.len();
+    match lines {
+        None => Some(json!({
+            "start": position_at(text, span.offset()),
+            "end": position_at(text, end),
+        })),
+        Some(lines) => {
+            let position = |offset: usize| match lines.position_of(offset) {
+                Some((None, line, column)) => Some(json!({
+                    "line": line - 1,
+                    "character": column - 1,
+                })),
+                _ => None,
+            };
+            Some(json!({
+                "start": position(span.offset())?,
+                "end": position(end)?,
+            }))
+        }
+    }
+}
+
+// What a built-in, macro or box is
+pub fn hover(document: &Document, position: &Value) -> Option<Value> {
+    let token = document.token_at(position)?;
+    let contents = match BUILTINS.iter().find(|(word, _, _)| *word == token.inner) {
+        Some((word, effect, doc)) if token.kind != TokenKind::String => {
+            format!("```roth\n{} {}\n```\n{}", word, effect, doc)
+        }
+        _ if token.kind == TokenKind::Ident => {
+            let mut preprocessor = document.preprocessor();
+            let _ = preprocessor.expand_recovering();
+            match preprocessor.macros().iter().find(|m| m.name == token.inner) {
+                Some(m) => {
+                    let effect = m
+                        .effect
+                        .as_ref()
+                        .map_or(String::new(), |e| format!(" {}", e));
+                    format!(
+                        "```roth\nmacro {}{}\n```\nexpands to `{}`",
+                        m.name, effect, m.body
+                    )
+                }
+                None => {
+                    let (val_type, _) = boxes(&document.tokens())
+                        .into_iter()
+                        .find(|(_, name)| name.inner == token.inner)?;
+                    format!("```roth\nbox {} {}\n```", val_type, token.inner)
+                }
+            }
+        }
+        _ => return None,
+    };
+
+    Some(json!({
+        "contents": { "kind": "markdown", "value": contents },
+        "range": {
+            "start": position_at(&document.text, token.span.offset()),
+            "end": position_at(&document.text, token.span.offset() + token.span.len()),
+        },
+    }))
+}
+
+// (type, name) of every box created in `tokens`
+fn boxes(tokens: &[Token]) -> Vec<(String, Token)> {
+    tokens
+        .windows(3)
+        .filter(|w| w[0].kind == TokenKind::Box && w[2].kind == TokenKind::Ident)
+        .map(|w| (w[1].inner.clone(), w[2].clone()))
+        .collect()
+}
+
+// Where a macro or box is defined, or the file an `include` points at
+pub fn definition(document: &Document, position: &Value) -> Option<Value> {
+    let dir = document.dir().unwrap_or_default();
+
+    // the cursor could be anywhere in `include("file")`, even on a space
+    let offset = offset_at(&document.text, position)?;
+    let byte_offset = document
+        .text
+        .char_indices()
+        .nth(offset)
+        .map_or(document.text.len(), |(idx, _)| idx);
+    for (file, span) in find_includes(&document.text) {
+        if span.offset() <= byte_offset && byte_offset < span.offset() + span.len() {
+            let search_path = search_path(&document.include_dirs);
+            let (_, path) = locate_include(&file, None, &dir, &search_path)?;
+            if !document.capabilities.can_include(&path) {
+                return None;
+            }
+            return Some(location(&path, &zero_range()));
+        }
+    }
+
+    let symbol = symbol_at(document, position)?;
+    Some(to_location(document, &symbol.definition))
+}
+
+// Where a macro or box is defined and every place it's used
+pub fn references(document: &Document, position: &Value, with_definition: bool) -> Option<Value> {
+    let symbol = symbol_at(document, position)?;
+    let mut found = Vec::new();
+    if with_definition {
+        found.push(to_location(document, &symbol.definition));
+    }
+    found.extend(symbol.uses.iter().map(|at| to_location(document, at)));
+
+    Some(json!(found))
+}
+
+fn symbol_at(document: &Document, position: &Value) -> Option<Symbol> {
+    let search_path = search_path(&document.include_dirs);
+    let index = SymbolIndex::new(
+        &document.text,
+        &document.dir().unwrap_or_default(),
+        &search_path,
+        &document.capabilities,
+    );
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+
+    index.at(None, line + 1, character + 1).cloned()
+}
+
+fn to_location(document: &Document, at: &Location) -> Value {
+    let range = json!({
+        "start": { "line": at.line - 1, "character": at.column - 1 },
+        "end": { "line": at.line - 1, "character": at.column - 1 + at.len },
+    });
+    match &at.file {
+        None => json!({ "uri": document.uri, "range": range }),
+        Some(file) => location(&document.dir().unwrap_or_default().join(file), &range),
+    }
+}
+
+// Keywords, macros and boxes
+pub fn completion(document: &Document) -> Value {
+    const KEYWORD: u8 = 14;
+    const FUNCTION: u8 = 3;
+    const VARIABLE: u8 = 6;
+
+    let mut items: Vec<Value> = KEYWORDS
+        .iter()
+        .map(|(keyword, _)| {
+            let detail = BUILTINS
+                .iter()
+                .find(|(word, _, _)| word == keyword)
+                .map(|(_, effect, _)| *effect);
+            json!({ "label": keyword, "kind": KEYWORD, "detail": detail })
+        })
+        .collect();
+
+    let mut preprocessor = document.preprocessor();
+    let _ = preprocessor.expand_recovering();
+    items.extend(preprocessor.macros().iter().map(|m| {
+        let detail = m.effect.as_ref().map(|effect| effect.to_string());
+        json!({ "label": m.name, "kind": FUNCTION, "detail": detail })
+    }));
+    items.extend(boxes(&document.tokens()).into_iter().map(
+        |(val_type, name)| json!({ "label": name.inner, "kind": VARIABLE, "detail": val_type }),
+    ));
+
+    json!(items)
+}
+
+// Every token as (delta line, delta start, length, type, modifiers)
+pub fn semantic_tokens(document: &Document) -> Value {
+    let mut preprocessor = document.preprocessor();
+    let _ = preprocessor.expand_recovering();
+    let macro_names = preprocessor.macro_names();
+
+    let mut data = Vec::new();
+    let (mut last_line, mut last_start) = (0, 0);
+    for token in document.tokens() {
+        // tokens can't go over more than one line
+        if token.inner.contains('\n') {
+            continue;
+        }
+
+        let token_type = match token.kind {
+            TokenKind::Number => 1,
+            TokenKind::String => 2,
+            TokenKind::Comment => 3,
+            TokenKind::Type
+            | TokenKind::TypeInt
+            | TokenKind::TypeStr
+            | TokenKind::TypeBool
+            | TokenKind::TypeBoxedInt
+            | TokenKind::TypeBoxedStr
+            | TokenKind::TypeBoxedBool => 4,
+            TokenKind::Ident if macro_names.contains(&token.inner) => 5,
+            TokenKind::Ident => 6,
+            TokenKind::Add
+            | TokenKind::Sub
+            | TokenKind::Mul
+            | TokenKind::Div
+            | TokenKind::Or
+            | TokenKind::And
+            | TokenKind::Not
+            | TokenKind::Eq
+            | TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessThanEq
+            | TokenKind::GreaterThanEq => 7,
+            TokenKind::Print
+            | TokenKind::Dup
+            | TokenKind::Drop
+            | TokenKind::Swap
+            | TokenKind::Over
+            | TokenKind::Rot
+            | TokenKind::Pack
+            | TokenKind::Unpack => 8,
+            _ => 0,
+        };
+
+        let (line, start) = line_and_character(&document.text, token.span.offset());
+        let delta_start = if line == last_line {
+            start - last_start
+        } else {
+            start
+        };
+        data.extend([
+            line - last_line,
+            delta_start,
+            token.span.len(),
+            token_type,
+            0,
+        ]);
+        last_line = line;
+        last_start = start;
+    }
+
+    json!({ "data": data })
+}
+
+fn location(path: &Path, range: &Value) -> Value {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    json!({ "uri": path_to_uri(&path), "range": range })
+}
+
+fn zero_range() -> Value {
+    json!({
+        "start": { "line": 0, "character": 0 },
+        "end": { "line": 0, "character": 0 },
+    })
+}
+
+// Spans count chars, and so do we (instead of UTF-16 code units)
+fn line_and_character(text: &str, offset: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut character = 0;
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += 1;
+        }
+    }
+
+    (line, character)
+}
+
+fn position_at(text: &str, offset: usize) -> Value {
+    let (line, character) = line_and_character(text, offset);
+    json!({ "line": line, "character": character })
+}
+
+fn offset_at(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+
+    let line_start: usize = text
+        .split('\n')
+        .take(line)
+        .map(|line| line.chars().count() + 1)
+        .sum();
+    Some(line_start + character)
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+
+    // undo percent encoding
+    let mut bytes = Vec::new();
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        if b == b'%' {
+            let hex: String = chars.by_ref().take(2).map(char::from).collect();
+            bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    format!("file://{}", path.to_string_lossy().replace(' ', "%20"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        completion, definition, diagnostics, hover, path_to_uri, references, semantic_tokens,
+        Document,
+    };
+    use crate::lsp::builtins::BUILTINS;
+    use crate::{capabilities::Capabilities, lexer::KEYWORDS};
+    use expect_test::expect;
+    use serde_json::json;
+
+    #[test]
+    fn test_features() {
+        let document = Document {
+            uri: String::from("untitled:1"),
+            text: String::from(
+                "macro sq ( int -- int ) dup * end\nbox type::int x\n3 sq dup\n\"a\" not",
+            ),
+            capabilities: Capabilities::default(),
+            include_dirs: Vec::new(),
+        };
+        let at = |line: u64, character: u64| json!({ "line": line, "character": character });
+
+        let diagnostics: Vec<String> = diagnostics(&document)
+            .iter()
+            .map(|d| format!("{} {}", d["code"], d["range"]))
+            .collect();
+        let hovers: Vec<String> = [at(2, 5), at(2, 3), at(1, 14)]
+            .iter()
+            .map(|position| hover(&document, position).unwrap()["contents"]["value"].to_string())
+            .collect();
+        let completions = completion(&document).as_array().unwrap().len();
+        let tokens = semantic_tokens(&document)["data"].as_array().unwrap().len() / 5;
+
+        let actual = format!(
+            "{:#?}\n{:#?}\ncompletions: {}, tokens: {}\ndefinition: {}\nreferences: {}",
+            diagnostics,
+            hovers,
+            completions,
+            tokens,
+            definition(&document, &at(2, 3)).unwrap(),
+            references(&document, &at(0, 7), false).unwrap(),
+        );
+
+        let expected = expect![[r#"
+            [
+                "\"roth::invalid_not\" {\"end\":{\"character\":3,\"line\":3},\"start\":{\"character\":0,\"line\":3}}",
+                "\"roth::unused_box\" {\"end\":{\"character\":3,\"line\":1},\"start\":{\"character\":0,\"line\":1}}",
+            ]
+            [
+                "\"```roth\\ndup ( a -- a a )\\n```\\ncopies the top value\"",
+                "\"```roth\\nmacro sq ( int -- int )\\n```\\nexpands to `dup *`\"",
+                "\"```roth\\nbox type::int x\\n```\"",
+            ]
+            completions: 43, tokens: 18
+            definition: {"range":{"end":{"character":8,"line":0},"start":{"character":6,"line":0}},"uri":"untitled:1"}
+            references: [{"range":{"end":{"character":4,"line":2},"start":{"character":2,"line":2}},"uri":"untitled:1"}]"#]];
+        expected.assert_eq(&actual);
+
+        // every keyword can be hovered
+        for (keyword, _) in KEYWORDS {
+            assert!(
+                BUILTINS.iter().any(|(word, _, _)| word == keyword),
+                "{}",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn test_sandboxed_includes() {
+        // `examples/` has files to include
+        let examples = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let document = |capabilities: Capabilities| Document {
+            uri: path_to_uri(&examples.join("t.roth")),
+            text: String::from("include(\"macro.roth\")\n1 2 add"),
+            capabilities,
+            include_dirs: Vec::new(),
+        };
+        let at = |line: u64, character: u64| json!({ "line": line, "character": character });
+
+        let actual: Vec<String> = [Capabilities::default(), Capabilities::default().sandboxed()]
+            .into_iter()
+            .map(|capabilities| {
+                let document = document(capabilities);
+                let codes: Vec<String> = diagnostics(&document)
+                    .iter()
+                    .map(|d| d["code"].to_string())
+                    .collect();
+                format!(
+                    "diagnostics: {}, include: {}, add: {}",
+                    codes.join(", "),
+                    definition(&document, &at(0, 10)).is_some(),
+                    definition(&document, &at(1, 5)).is_some(),
+                )
+            })
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "diagnostics: \"roth::leftover_values\", include: true, add: true",
+                "diagnostics: \"roth::include_not_allowed\", include: false, add: false",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -0,0 +1,228 @@
+mod builtins;
+mod features;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use miette::Result;
+use serde_json::{json, Value};
+
+use crate::{capabilities::Capabilities, cli::Options};
+use features::{Document, TOKEN_TYPES};
+
+// `roth lsp`: a language server talking JSON-RPC over stdin and stdout
+pub fn lsp(options: &Options) -> Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server {
+        capabilities: options.capabilities.clone(),
+        include_dirs: options.include_dirs.clone(),
+        ..Server::default()
+    };
+
+    while let Some(message) = read_message(&mut input) {
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            // there's no id to answer, but the client should still hear about it
+            Err(error) => vec![json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": error },
+            })],
+        };
+        for reply in replies {
+            send(&reply);
+        }
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    exited: bool,
+    // `--sandbox`, `--allow-include=<dir>` and `-I <dir>`, for every document
+    capabilities: Capabilities,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Server {
+    // Everything to send back for `message`: a response to a request, and
+    // diagnostics whenever a document changes
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let position = &params["position"];
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // the whole document is sent on every change
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": {},
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "roth" },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"][0]["text"].as_str(),
+                };
+                let document = Document {
+                    uri: uri.to_string(),
+                    text: text.unwrap_or_default().to_string(),
+                    capabilities: self.capabilities.clone(),
+                    include_dirs: self.include_dirs.clone(),
+                };
+                self.documents.insert(uri.to_string(), document);
+                return self.publish_diagnostics(uri).into_iter().collect();
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                // clear what we said about it
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/hover"
+            | "textDocument/definition"
+            | "textDocument/references"
+            | "textDocument/completion"
+            | "textDocument/semanticTokens/full" => match self.document(uri) {
+                None => Value::Null,
+                Some(document) => match method {
+                    "textDocument/hover" => features::hover(document, position).into(),
+                    "textDocument/definition" => features::definition(document, position).into(),
+                    "textDocument/references" => {
+                        let with_definition =
+                            params["context"]["includeDeclaration"].as_bool() != Some(false);
+                        features::references(document, position, with_definition).into()
+                    }
+                    "textDocument/completion" => features::completion(document),
+                    _ => features::semantic_tokens(document),
+                },
+            },
+            // notifications we don't care about, like `initialized`
+            _ if message.get("id").is_none() => return Vec::new(),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("unknown method `{}`", method) },
+                })]
+            }
+        };
+
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+
+    fn document(&self, uri: &str) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Option<Value> {
+        let document = self.document(uri)?;
+        Some(notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": features::diagnostics(document) }),
+        ))
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+// Messages are JSON with a `Content-Length` header in front. `None` once the
+// client has gone away, and an error for a message we couldn't make sense of,
+// which the next one doesn't depend on.
+fn read_message(input: &mut impl BufRead) -> Option<Result<Value, String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = match length {
+        Some(length) => length,
+        None => return Some(Err(String::from("missing `Content-Length` header"))),
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    Some(serde_json::from_slice(&body).map_err(|e| format!("invalid JSON: {}", e)))
+}
+
+fn send(message: &Value) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::lsp::read_message;
+    use expect_test::expect;
+
+    #[test]
+    fn test_read_message() {
+        let message = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let input = [
+            message(r#"{"id":1}"#),
+            message(r#"{"id":"#),
+            String::from("Content-Type: text/plain\r\n\r\n"),
+            message(r#"{"id":2}"#),
+        ]
+        .concat();
+
+        let mut input = Cursor::new(input);
+        let actual: Vec<String> = std::iter::from_fn(|| read_message(&mut input))
+            .map(|message| match message {
+                Ok(message) => message.to_string(),
+                Err(error) => error,
+            })
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "{\"id\":1}",
+                "invalid JSON: EOF while parsing a value at line 1 column 6",
+                "missing `Content-Length` header",
+                "{\"id\":2}",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
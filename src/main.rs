@@ -1,88 +1,255 @@
+mod analysis;
+mod capabilities;
+mod checker;
+mod cli;
+mod coverage;
+mod debugger;
 mod error;
+mod fmt;
+mod json;
 mod lexer;
+mod limits;
+mod lint;
+mod lsp;
+mod modules;
 mod op;
 mod parser;
 mod preprocessor;
+mod profile;
+mod repl;
 mod runtime;
 mod stack;
+mod suggest;
+mod symbols;
+mod testing;
 mod token;
+mod trace;
 mod val;
 
 use miette::Result;
-use preprocessor::PreProcessor;
-use reedline::{DefaultPrompt, Reedline, Signal};
 use runtime::Runtime;
-use std::{env::set_current_dir, path::PathBuf, process};
+use std::{path::Path, process};
 
+use cli::{Command, Input, Options};
+use coverage::Coverage;
 use lexer::Lexer;
 use parser::Parser;
+use profile::{ProfileOutput, Profiler};
 use stack::Stack;
+use symbols::{Symbol, SymbolIndex};
+use trace::Tracer;
 
-use crate::error::ParseError;
+use crate::error::{CliError, Errors, Located};
 
 fn main() -> Result<()> {
-    // if file was passed in stdin, then eval without repl
-    let args: Vec<String> = std::env::args().collect();
-
-    if args.len() == 2 {
-        let file_name = args.get(1).unwrap();
-        match std::fs::read_to_string(file_name) {
-            Ok(contents) => {
-                // we have to set our current working directory to where this file is
-                let path = PathBuf::from(file_name);
-                match path.parent() {
-                    Some(dir) => {
-                        if set_current_dir(dir).is_err() {
-                            todo!("failed to set current dir to file's parent");
-                        }
-                    }
-                    None => todo!("file name doesn't have parent folder"),
-                }
-                eval(contents)?;
-                Ok(())
+    let mut args: Vec<String> = std::env::args().collect();
+    let options = Options::parse(&mut args)?;
+    let (command, input) = cli::parse_command(&args)?;
+
+    match run(command, input.as_ref(), &options) {
+        Err(report) if options.json_messages => {
+            json::print_report(&report, input.as_ref().map_or("", |input| input.name()));
+            process::exit(1);
+        }
+        result => result,
+    }
+}
+
+fn run(command: Command, input: Option<&Input>, options: &Options) -> Result<()> {
+    let input = match (&command, input) {
+        (Command::Repl, _) => return repl::repl(options),
+        (Command::Test, input) => return testing::run_tests(input, options),
+        (Command::Lsp, _) => return lsp::lsp(options),
+        (_, Some(input)) => input,
+        // `parse_command` makes sure every other command gets a program
+        (_, None) => unreachable!("no program for {:?}", command),
+    };
+    let source = input.read()?;
+
+    match command {
+        Command::Run => eval(source, input, options).map(|_| ()),
+        Command::Check => check(source, input, options),
+        Command::Debug => {
+            debugger::debug(options.preprocessor(input, &source), input.name(), options)
+        }
+        Command::Fmt => format(source, input, options),
+        Command::Def(name) => {
+            let symbol = find_symbol(&source, input, &name, options)?;
+            println!("{}", symbol.definition.describe(input.name()));
+            Ok(())
+        }
+        Command::Refs(name) => {
+            let symbol = find_symbol(&source, input, &name, options)?;
+            for at in std::iter::once(&symbol.definition).chain(&symbol.uses) {
+                println!("{}", at.describe(input.name()));
             }
-            _ => Err(ParseError::CannotReadFile(file_name.to_string()).into()),
+            Ok(())
+        }
+        Command::Expand => {
+            print!("{}", options.preprocessor(input, &source).expand()?);
+            Ok(())
         }
-    } else {
-        repl()
+        Command::Tokens => {
+            let mut preprocessor = options.preprocessor(input, &source);
+            let expanded_source = preprocessor.expand()?;
+            let tokens = Lexer::new(expanded_source.as_str())
+                .lex()
+                .map_err(Located::wrap(preprocessor.lines()))?;
+            for token in tokens {
+                let (line, column) =
+                    json::position(&expanded_source, token.span.offset()).unwrap_or_default();
+                println!("{}:{}\t{:?}\t{:?}", line, column, token.kind, token.inner);
+            }
+            Ok(())
+        }
+        Command::Ops => {
+            let mut preprocessor = options.preprocessor(input, &source);
+            let expanded_source = preprocessor.expand()?;
+            let located = Located::wrap(preprocessor.lines());
+            let tokens = Lexer::new(expanded_source.as_str())
+                .lex()
+                .map_err(&located)?;
+            let mut parser = Parser::new(tokens, expanded_source.clone())
+                .with_inclusions(preprocessor.inclusions().to_vec())
+                .with_expansions(preprocessor.expansions().to_vec());
+            let ops = parser.parse().map_err(&located)?;
+            for op in ops {
+                let (line, column) =
+                    json::position(&expanded_source, op.span.offset()).unwrap_or_default();
+                println!("{}:{}\t{:?}", line, column, op.kind);
+            }
+            Ok(())
+        }
+        Command::Repl | Command::Test | Command::Lsp => unreachable!(),
     }
 }
 
-fn repl() -> Result<()> {
-    let mut line_editor = Reedline::create().unwrap();
-    let prompt = DefaultPrompt::default();
+// Includes are next to the program
+fn find_symbol(source: &str, input: &Input, name: &str, options: &Options) -> Result<Symbol> {
+    let search_path = preprocessor::search_path(&options.include_dirs);
+    let dir = input
+        .path()
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""));
+    let index = SymbolIndex::new(source, dir, &search_path, &options.capabilities);
+    match index.get(name) {
+        Some(symbol) => Ok(symbol.clone()),
+        None => {
+            let help = match suggest::did_you_mean(name, &index.names()) {
+                Some(suggestion) => format!("did you mean `{}`?", suggestion),
+                None => String::from("it isn't defined in the program or any file it includes"),
+            };
+            Err(CliError::UnknownSymbol(name.to_string(), help).into())
+        }
+    }
+}
 
-    loop {
-        let sig = line_editor.read_line(&prompt).unwrap();
-        match sig {
-            Signal::Success(buffer) => {
-                if buffer == "exit" {
-                    process::exit(0);
-                }
+// Files are formatted in place, anything else is printed
+fn format(source: String, input: &Input, options: &Options) -> Result<()> {
+    let formatted = fmt::format(&source)?;
 
-                match eval(buffer) {
-                    Ok(mut stack) => {
-                        if let Ok(v) = stack.pop() {
-                            println!("{}", v);
-                        }
-                    }
-                    Err(e) => println!("{:?}", e),
-                }
+    match input {
+        _ if options.check_formatting => {
+            if formatted != source {
+                return Err(CliError::WouldReformat(input.name().to_string()).into());
             }
-            Signal::CtrlD | Signal::CtrlC => {
-                line_editor.print_crlf().unwrap();
-            }
-            Signal::CtrlL => {
-                line_editor.clear_screen().unwrap();
+        }
+        Input::File(file_name) => {
+            if formatted != source && std::fs::write(file_name, formatted).is_err() {
+                return Err(CliError::CannotWriteFile(file_name.clone()).into());
             }
         }
+        _ => print!("{}", formatted),
+    }
+
+    Ok(())
+}
+
+// Report every stack underflow and type error we can find without running anything,
+// along with anything the lints don't like
+fn check(source: String, input: &Input, options: &Options) -> Result<()> {
+    let analysis = analysis::analyze(options.preprocessor(input, &source), &options.lint_levels)?;
+    let located = Located::wrap(&analysis.lines);
+    for warning in analysis.warnings {
+        options.print_report(&located(warning), input.name());
     }
+
+    Errors::into_result(analysis.errors).map_err(located)
 }
 
-fn eval(source: String) -> Result<Stack> {
-    let expanded_source = PreProcessor::new(source.as_str()).expand()?;
+// With `--debug` every macro with a stack effect is checked against it as it runs
+fn eval(source: String, input: &Input, options: &Options) -> Result<Stack> {
+    let file_name = input.name();
+    let mut preprocessor = options.preprocessor(input, &source);
+    let (expanded_source, errors) = preprocessor.expand_recovering()?;
+    // from here on errors are about the expanded program
+    let located = Located::wrap(preprocessor.lines());
+    Errors::into_result(errors).map_err(&located)?;
+
+    let tokens = Lexer::new(expanded_source.as_str())
+        .lex()
+        .map_err(&located)?;
+    let ops = Parser::new(tokens, expanded_source.clone())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_expansions(preprocessor.expansions().to_vec())
+        .parse()
+        .map_err(&located)?;
+    let coverage = options.coverage.as_ref().map(|_| {
+        Coverage::new(
+            file_name,
+            &source,
+            preprocessor.lines().clone(),
+            preprocessor.expansions().to_vec(),
+            &ops,
+        )
+    });
+    let mut runtime = Runtime::new(expanded_source.clone(), ops)
+        .with_expansions(preprocessor.expansions().to_vec())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_macro_names(preprocessor.macro_names())
+        .with_scopes(preprocessor.scopes().clone())
+        .with_limits(options.limits)
+        .with_capabilities(options.capabilities.clone());
+    if options.debug {
+        runtime = runtime.with_effect_checks();
+    }
+    if let Some(format) = options.trace {
+        runtime = runtime.with_tracer(Tracer::new(
+            format,
+            file_name,
+            preprocessor.lines().clone(),
+            preprocessor.expansions().to_vec(),
+        ));
+    }
+    if options.profile.is_some() {
+        runtime = runtime.with_profiler(Profiler::new(
+            file_name,
+            preprocessor.lines().clone(),
+            preprocessor.expansions().to_vec(),
+        ));
+    }
+
+    if let Some(coverage) = coverage {
+        runtime = runtime.with_coverage(coverage);
+    }
+
+    // a profile of a program that failed still says where the time went, and
+    // coverage says how far it got
+    let result = runtime.run().map_err(&located);
+    if let (Some(output), Some(coverage)) = (&options.coverage, runtime.coverage()) {
+        coverage::report(output, std::slice::from_ref(coverage))?;
+    }
+    if let (Some(output), Some(profiler)) = (&options.profile, runtime.profiler()) {
+        match output {
+            ProfileOutput::Report => eprint!("{}", profiler.report(&expanded_source)),
+            ProfileOutput::Folded(file) => {
+                if std::fs::write(file, profiler.folded(&expanded_source)).is_err() {
+                    let file = file.display().to_string();
+                    return Err(CliError::CannotWriteFile(file).into());
+                }
+            }
+        }
+    }
 
-    let tokens = Lexer::new(expanded_source.as_str()).lex()?;
-    let ops = Parser::new(tokens, expanded_source.clone()).parse()?;
-    Runtime::new(expanded_source, ops).run()
+    result
 }
@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+
+use miette::SourceSpan;
+
+use crate::{error::ParseError, suggest::did_you_mean};
+
+// `name` as it's called from outside of `module` (`None` for the program)
+pub fn qualify(module: Option<&str>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}::{}", module, name),
+        None => name.to_string(),
+    }
+}
+
+// What the module declared as `name` is called internally, which is its name
+// unless a module in another file already has it. Every stage hands these out in
+// the order the modules are first brought in, so they agree.
+pub fn module_key(name: &str, taken: &[String]) -> String {
+    let clashes = taken
+        .iter()
+        .filter(|key| key.split('#').next() == Some(name))
+        .count();
+    match clashes {
+        0 => name.to_string(),
+        _ => format!("{}#{}", name, clashes + 1),
+    }
+}
+
+// The file in an `import("file")` token
+pub fn imported_file(statement: &str) -> &str {
+    statement
+        .trim_start_matches("import(")
+        .trim_end_matches(')')
+        .trim_matches('"')
+}
+
+// What a name written somewhere refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Name(String),
+    // a name from another module that it doesn't export
+    NotExported(String),
+    // `alias::name` without a module imported as `alias`
+    UnknownModule,
+}
+
+// Which module every part of the expanded program belongs to, and what names
+// each module can see: its own, and what the modules it imports export (as
+// `alias::name`). The program and the modules can't see each other's names
+// otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Scopes {
+    // (start, end, module) of every file in the output, outer ones first
+    files: Vec<(usize, usize, Option<String>)>,
+    // (start, end, module the macro is from) of every expansion, outer ones first
+    expansions: Vec<(usize, usize, Option<String>)>,
+    // (module importing, alias, module imported)
+    aliases: Vec<(Option<String>, String, String)>,
+    // qualified names of everything modules export
+    exports: HashSet<String>,
+}
+
+impl Scopes {
+    pub fn import(&mut self, importing: Option<&str>, alias: &str, module: &str) {
+        self.aliases.push((
+            importing.map(String::from),
+            alias.to_string(),
+            module.to_string(),
+        ));
+    }
+
+    pub fn export(&mut self, name: String) {
+        self.exports.insert(name);
+    }
+
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.exports.contains(name)
+    }
+
+    // Where files and macros ended up in the output, once it's all expanded
+    pub fn with_files(mut self, files: Vec<(usize, usize, Option<String>)>) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn with_expansions(mut self, expansions: Vec<(usize, usize, Option<String>)>) -> Self {
+        self.expansions = expansions;
+        self
+    }
+
+    // The module the code at `offset` in the output was written in
+    pub fn module_at(&self, offset: usize) -> Option<&str> {
+        let within =
+            |(start, end, _): &&(usize, usize, Option<String>)| *start <= offset && offset < *end;
+
+        // a macro's body belongs to where the macro is defined
+        self.expansions
+            .iter()
+            .rev()
+            .find(within)
+            .or_else(|| self.files.iter().rev().find(within))
+            .and_then(|(_, _, module)| module.as_deref())
+    }
+
+    // What `word`, written in `module`, refers to
+    pub fn resolve(&self, word: &str, module: Option<&str>) -> Resolved {
+        let (alias, name) = match word.split_once("::") {
+            Some(qualified) => qualified,
+            None => return Resolved::Name(qualify(module, word)),
+        };
+
+        match self.imported(alias, module) {
+            Some(imported) if Some(imported) == module => Resolved::Name(qualify(module, name)),
+            Some(imported) => {
+                let qualified = qualify(Some(imported), name);
+                match self.exports.contains(&qualified) {
+                    true => Resolved::Name(qualified),
+                    false => Resolved::NotExported(qualified),
+                }
+            }
+            None => Resolved::UnknownModule,
+        }
+    }
+
+    // The error for a name `resolve` turned down
+    pub fn error(
+        &self,
+        word: &str,
+        module: Option<&str>,
+        source: &str,
+        span: SourceSpan,
+    ) -> ParseError {
+        let (alias, name) = word.split_once("::").unwrap_or(("", word));
+        match self.imported(alias, module) {
+            Some(imported) => {
+                let prefix = format!("{}::", imported);
+                let mut exports: Vec<&str> = self
+                    .exports
+                    .iter()
+                    .filter_map(|export| export.strip_prefix(&prefix))
+                    .collect();
+                exports.sort();
+                let help = match (did_you_mean(name, &exports), exports.is_empty()) {
+                    (Some(suggestion), _) => format!("did you mean `{}::{}`?", alias, suggestion),
+                    (None, true) => format!("`{}` doesn't export anything", alias),
+                    (None, false) => format!("`{}` exports {}", alias, exports.join(", ")),
+                };
+                ParseError::NotExported(source.to_string(), word.to_string(), help, span)
+            }
+            None => {
+                let aliases: Vec<&str> = self
+                    .aliases
+                    .iter()
+                    .filter(|(importing, _, _)| importing.as_deref() == module)
+                    .map(|(_, alias, _)| alias.as_str())
+                    .collect();
+                let help = match did_you_mean(alias, &aliases) {
+                    Some(suggestion) => format!("did you mean `{}::{}`?", suggestion, name),
+                    None => format!("import it with `import(\"<file>\") as {}`", alias),
+                };
+                ParseError::UnknownModule(source.to_string(), alias.to_string(), help, span)
+            }
+        }
+    }
+
+    // `alias::word` if `name`, as the parser resolved it, is a name without a
+    // prefix that a module imported where it's written exports as `word`
+    pub fn imported_name(&self, name: &str) -> Option<String> {
+        let (module, word) = match name.rsplit_once("::") {
+            Some((module, word)) => (Some(module), word),
+            None => (None, name),
+        };
+
+        self.aliases
+            .iter()
+            .rev()
+            .filter(|(importing, _, _)| importing.as_deref() == module)
+            .find(|(_, _, imported)| self.exports.contains(&qualify(Some(imported), word)))
+            .map(|(_, alias, _)| format!("{}::{}", alias, word))
+    }
+
+    // The module `alias` stands for in `module`, with later imports winning.
+    // A module can call itself by its own name.
+    fn imported<'a>(&'a self, alias: &str, module: Option<&'a str>) -> Option<&'a str> {
+        self.aliases
+            .iter()
+            .rev()
+            .find(|(importing, name, _)| importing.as_deref() == module && name == alias)
+            .map(|(_, _, imported)| imported.as_str())
+            .or_else(|| module.filter(|module| *module == alias))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{lexer::Lexer, parser::Parser, preprocessor::PreProcessor, runtime::Runtime};
+    use expect_test::expect;
+    use miette::Result;
+
+    fn run(source: &str) -> Result<String> {
+        let mut preprocessor = PreProcessor::new(source);
+        let source = preprocessor.expand()?;
+        let tokens = Lexer::new(&source).lex()?;
+        let ops = Parser::new(tokens, source.clone())
+            .with_inclusions(preprocessor.inclusions().to_vec())
+            .with_expansions(preprocessor.expansions().to_vec())
+            .parse()?;
+        let mut runtime = Runtime::new(source, ops).with_scopes(preprocessor.scopes().clone());
+        runtime.run()?;
+
+        let stack: Vec<String> = runtime.stack().iter().map(|v| v.literal()).collect();
+        Ok(stack.join(" "))
+    }
+
+    #[test]
+    fn test_modules() {
+        let dir = std::env::temp_dir().join(format!("roth-modules-{}", std::process::id()));
+        let files = [
+            (
+                "math.roth",
+                "module math\nbox type::int calls\n0 calls pack\nmacro twice dup + end\nexport macro sq dup * calls unpack 1 + calls pack end\nexport box type::int total\n",
+            ),
+            (
+                "stats.roth",
+                "module stats\nimport(\"math.roth\") as m\nbox type::int calls\nexport macro sumsq m::sq swap m::sq + end\n",
+            ),
+            ("plain.roth", "macro one 1 end\n"),
+        ];
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        let import = |file: &str| format!("import(\"{}\")", dir.join(file).display());
+
+        let cases = [
+            // every module has its own `calls`
+            format!(
+                "{}\n{} as st\nbox type::int calls\n3 math::sq 1 2 st::sumsq 4 math::total pack math::total unpack",
+                import("math.roth"),
+                import("stats.roth"),
+            ),
+            format!("{}\n2 math::twice", import("math.roth")),
+            format!("{}\nmath::calls unpack", import("math.roth")),
+            format!("{} as m\n2 math::sq", import("math.roth")),
+            // exported names need the module's prefix
+            format!("{}\n2 sq", import("math.roth")),
+            format!("{} as m\n2 sq", import("math.roth")),
+            format!("{}\n5 total pack", import("math.roth")),
+            format!("{}\n2 twice", import("math.roth")),
+            import("plain.roth"),
+            String::from("export macro sq dup * end"),
+        ];
+
+        let actual: Vec<String> = cases
+            .iter()
+            .map(|source| match run(source) {
+                Ok(stack) => stack,
+                Err(e) => format!(
+                    "{}: {} ({})",
+                    e.code().unwrap(),
+                    e,
+                    e.help().map(|help| help.to_string()).unwrap_or_default()
+                ),
+            })
+            .collect();
+
+        let _ = fs::remove_dir_all(&dir);
+        let actual = format!("{:#?}", actual).replace(&dir.display().to_string(), "<tmp>");
+        let expected = expect![[r#"
+            [
+                "9 5 4",
+                "roth::not_exported: `math::twice` isn't exported (`math` exports sq, total)",
+                "roth::not_exported: `math::calls` isn't exported (`math` exports sq, total)",
+                "roth::unknown_module: No module called `math` here (import it with `import(\"<file>\") as math`)",
+                "roth::unknown_box: Unknown box (did you mean `math::sq`? Names from imported modules need their prefix)",
+                "roth::unknown_box: Unknown box (did you mean `m::sq`? Names from imported modules need their prefix)",
+                "roth::unknown_box: Unknown box (did you mean `math::total`? Names from imported modules need their prefix)",
+                "roth::unknown_box: Unknown box (couldn't find any boxes with this name. Is it a typo?)",
+                "roth::not_a_module: `<tmp>/plain.roth` isn't a module (only files that start with `module <name>` can be imported. Use `include` to pull in a file as it is)",
+                "roth::misplaced_export: `export` has to go in front of a macro or box in a module (i.e. `export macro square dup * end`, in a file that starts with `module <name>`)",
+            ]"#]];
+        expected.assert_eq(&actual);
+    }
+}
@@ -51,5 +51,10 @@ pub enum OpKind {
     Rot,
     GetType,
     Assert,
-    Until
+    AssertEq,
+    AssertNe,
+    AssertType,
+    AssertStackDepth,
+    Breakpoint,
+    Until,
 }
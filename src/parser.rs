@@ -1,7 +1,11 @@
 use std::collections::VecDeque;
 
-use crate::error::ParseError;
+use miette::Result;
+
+use crate::error::{Errors, ParseError};
+use crate::modules::{imported_file, module_key, qualify, Resolved, Scopes};
 use crate::op::{Op, OpKind};
+use crate::preprocessor::{Expansion, Inclusion};
 use crate::token::{Token, TokenKind};
 use crate::val::ValType;
 
@@ -9,6 +13,22 @@ use crate::val::ValType;
 pub struct Parser {
     tokens: VecDeque<Token>,
     source_code: String,
+    tests: Vec<TestCase>,
+    // which module each box name belongs to
+    scopes: Scopes,
+    // where included files ended up, for working out which are modules
+    inclusions: Vec<Inclusion>,
+}
+
+// A `test "name" ... end` block. Tests aren't part of the program, and each one
+// gets run on its own by `roth test`.
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub ops: VecDeque<Op>,
+    // how many of the program's ops come before the test, which get run first
+    // to set up its boxes
+    pub setup: usize,
 }
 
 impl Parser {
@@ -16,19 +36,97 @@ impl Parser {
         Self {
             tokens: tokens.into(),
             source_code,
+            tests: Vec::new(),
+            scopes: Scopes::default(),
+            inclusions: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<VecDeque<Op>, ParseError> {
+    // Start from what's already known about modules, for parsing part of one
+    pub fn with_scopes(mut self, scopes: Scopes) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    // Files that start with `module <name>` are modules, and box names in them
+    // get qualified with the module like the preprocessor does for macros
+    pub fn with_inclusions(mut self, inclusions: Vec<Inclusion>) -> Self {
+        self.inclusions = inclusions;
+        self
+    }
+
+    // A macro's body is in the module the macro is from, wherever it's used
+    pub fn with_expansions(mut self, expansions: Vec<Expansion>) -> Self {
+        let expansions = expansions
+            .iter()
+            .map(|e| {
+                // names of macros from modules start with the module
+                let module = e
+                    .name
+                    .split_once("::")
+                    .map(|(module, _)| module.to_string());
+                (e.span.offset(), e.span.offset() + e.span.len(), module)
+            })
+            .collect();
+        self.scopes = std::mem::take(&mut self.scopes).with_expansions(expansions);
+        self
+    }
+
+    // The tests found while parsing
+    pub fn take_tests(&mut self) -> Vec<TestCase> {
+        std::mem::take(&mut self.tests)
+    }
+
+    pub fn parse(&mut self) -> Result<VecDeque<Op>> {
+        let (ops, errors) = self.parse_recovering();
+        Errors::into_result(errors.into_iter().map(|e| e.into()).collect())?;
+
+        Ok(ops)
+    }
+
+    // Keep going after an error, so every problem in the file gets reported.
+    // Whatever tokens a bad statement managed to use up are skipped.
+    pub fn parse_recovering(&mut self) -> (VecDeque<Op>, Vec<ParseError>) {
         // Split on whitespace
         let mut ops = VecDeque::new();
+        let mut errors = self.resolve_modules();
 
         while let Some(token) = self.tokens.pop_front() {
             let op_kind = match token.kind {
+                // macros have already been expanded by the preprocessor, so
+                // their definitions can be skipped entirely
                 TokenKind::Macro => {
+                    self.skip_macro_definition();
+                    continue;
+                }
+                TokenKind::Comment => continue,
+                // `resolve_modules` already went through these
+                TokenKind::Module => {
+                    self.tokens.pop_front();
                     continue;
                 }
-                TokenKind::Ident => OpKind::PushBox { name: token.inner },
+                TokenKind::Import => {
+                    if self.tokens.front().is_some_and(|t| t.inner == "as") {
+                        self.tokens.pop_front();
+                        self.tokens.pop_front();
+                    }
+                    continue;
+                }
+                TokenKind::Export => continue,
+                TokenKind::Test => {
+                    match self.parse_test(token, ops.len()) {
+                        Ok(test) => self.tests.push(test),
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+                TokenKind::Ident => match self.box_name(&token) {
+                    Ok(name) => OpKind::PushBox { name },
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                },
                 TokenKind::Add => OpKind::Add,
                 TokenKind::Sub => OpKind::Sub,
                 TokenKind::Mul => OpKind::Mul,
@@ -57,7 +155,18 @@ impl Parser {
                 TokenKind::TypeBoxedStr => OpKind::PushTypeBoxedStr,
                 TokenKind::TypeBoxedBool => OpKind::PushTypeBoxedBool,
                 TokenKind::Assert => OpKind::Assert,
-                TokenKind::Box => self.parse_create_box(token.clone())?,
+                TokenKind::AssertEq => OpKind::AssertEq,
+                TokenKind::AssertNe => OpKind::AssertNe,
+                TokenKind::AssertType => OpKind::AssertType,
+                TokenKind::AssertStackDepth => OpKind::AssertStackDepth,
+                TokenKind::Breakpoint => OpKind::Breakpoint,
+                TokenKind::Box => match self.parse_create_box(token.clone()) {
+                    Ok(op_kind) => op_kind,
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                },
                 TokenKind::Pack => OpKind::Pack,
                 TokenKind::Unpack => OpKind::Unpack,
                 TokenKind::Until => OpKind::Until,
@@ -81,7 +190,190 @@ impl Parser {
             ops.push_back(Op::new(token.span, op_kind));
         }
 
-        Ok(ops)
+        (ops, errors)
+    }
+
+    fn skip_macro_definition(&mut self) {
+        let mut depth: usize = 0;
+        while let Some(token) = self.tokens.pop_front() {
+            match token.kind {
+                TokenKind::If | TokenKind::Until => depth += 1,
+                TokenKind::End if depth == 0 => break,
+                TokenKind::End => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    //  test "name" ... end
+    fn parse_test(&mut self, test_token: Token, setup: usize) -> Result<TestCase, ParseError> {
+        let name = match self.tokens.front() {
+            Some(Token {
+                kind: TokenKind::String,
+                ..
+            }) => self.tokens.pop_front().unwrap(),
+            _ => {
+                return Err(ParseError::TestsNeedNames(
+                    self.source_code.clone(),
+                    test_token.span,
+                ))
+            }
+        };
+
+        let mut body = Vec::new();
+        let mut depth: usize = 0;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => {
+                    return Err(ParseError::UnclosedTest(
+                        self.source_code.clone(),
+                        test_token.span,
+                    ))
+                }
+            };
+            match token.kind {
+                TokenKind::If | TokenKind::Until | TokenKind::Macro => depth += 1,
+                TokenKind::End if depth == 0 => break,
+                TokenKind::End => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        let mut parser =
+            Parser::new(body, self.source_code.clone()).with_scopes(self.scopes.clone());
+        let (ops, errors) = parser.parse_recovering();
+        // only the first error, the rest get reported when the test is run anyway
+        if let Some(error) = errors.into_iter().next() {
+            return Err(error);
+        }
+
+        Ok(TestCase {
+            name: name.inner,
+            ops,
+            setup,
+        })
+    }
+
+    // Work out which module every file is part of, and what each module imports
+    // and exports, before any names need resolving
+    fn resolve_modules(&mut self) -> Vec<ParseError> {
+        let tokens: Vec<Token> = self.tokens.iter().cloned().collect();
+
+        // (start, end, module) of every file, and (file, name, key) of modules
+        let mut files: Vec<(usize, usize, Option<String>)> = Vec::new();
+        let mut modules: Vec<(String, String, String)> = Vec::new();
+        for inclusion in &self.inclusions {
+            let start = inclusion.span.offset();
+            let end = start + inclusion.span.len();
+            let first = tokens
+                .iter()
+                .position(|t| start <= t.span.offset() && t.span.offset() < end);
+            let declared = first.and_then(|idx| match (&tokens[idx], tokens.get(idx + 1)) {
+                (keyword, Some(name))
+                    if keyword.kind == TokenKind::Module && name.kind == TokenKind::Ident =>
+                {
+                    Some(name.inner.clone())
+                }
+                _ => None,
+            });
+
+            let module = match declared {
+                Some(name) => {
+                    let taken: Vec<String> =
+                        modules.iter().map(|(_, _, key)| key.clone()).collect();
+                    let key = module_key(&name, &taken);
+                    modules.push((inclusion.file.clone(), name, key.clone()));
+                    Some(key)
+                }
+                // other files are part of whatever included them
+                None => files
+                    .iter()
+                    .rev()
+                    .find(|(outer_start, outer_end, _)| *outer_start <= start && start < *outer_end)
+                    .and_then(|(_, _, module)| module.clone()),
+            };
+            files.push((start, end, module));
+        }
+        if !self.inclusions.is_empty() {
+            self.scopes = std::mem::take(&mut self.scopes).with_files(files);
+        }
+
+        let mut errors = Vec::new();
+        for (idx, token) in tokens.iter().enumerate() {
+            let module = self.scopes.module_at(token.span.offset()).map(String::from);
+            match token.kind {
+                // `import("file")`, maybe followed by `as <alias>`
+                TokenKind::Import => {
+                    let file = imported_file(&token.inner);
+                    match modules
+                        .iter()
+                        .find(|(module_file, _, _)| module_file == file)
+                    {
+                        Some((_, name, key)) => {
+                            let alias = match (tokens.get(idx + 1), tokens.get(idx + 2)) {
+                                (Some(keyword), Some(alias)) if keyword.inner == "as" => {
+                                    &alias.inner
+                                }
+                                _ => name,
+                            };
+                            self.scopes.import(module.as_deref(), alias, key);
+                        }
+                        None => errors.push(ParseError::NotAModule(
+                            self.source_code.clone(),
+                            file.to_string(),
+                            token.span.clone(),
+                        )),
+                    }
+                }
+                // `export macro <name>` or `export box <type> <name>`
+                TokenKind::Export => {
+                    let name = match tokens.get(idx + 1).map(|t| t.kind) {
+                        Some(TokenKind::Macro) => tokens.get(idx + 2),
+                        Some(TokenKind::Box) => tokens.get(idx + 3),
+                        _ => None,
+                    };
+                    match (&module, name) {
+                        (Some(_), Some(name)) => {
+                            self.scopes.export(qualify(module.as_deref(), &name.inner))
+                        }
+                        _ => errors.push(ParseError::MisplacedExport(
+                            self.source_code.clone(),
+                            token.span.clone(),
+                        )),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        errors
+    }
+
+    // `name` in a module is `module::name` everywhere else
+    fn box_name(&self, token: &Token) -> Result<String, ParseError> {
+        let module = self.scopes.module_at(token.span.offset());
+        match self.scopes.resolve(&token.inner, module) {
+            Resolved::Name(name) => Ok(name),
+            _ => {
+                Err(self
+                    .scopes
+                    .error(&token.inner, module, &self.source_code, token.span.clone()))
+            }
+        }
+    }
+
+    // When the type of a box is wrong, its name would otherwise get parsed as a
+    // use of a box that doesn't exist
+    fn skip_box_name(&mut self) {
+        if let Some(Token {
+            kind: TokenKind::Ident,
+            ..
+        }) = self.tokens.front()
+        {
+            self.tokens.pop_front();
+        }
     }
 
     // Boxes are created via the following syntax
@@ -93,18 +385,24 @@ impl Parser {
                 TokenKind::TypeStr => ValType::Str,
                 TokenKind::TypeBool => ValType::Bool,
                 TokenKind::TypeBoxedInt | TokenKind::TypeBoxedStr | TokenKind::TypeBoxedBool => {
-                    return Err(ParseError::UnboxableType(self.source_code.clone(), type_token.span))
+                    self.skip_box_name();
+                    return Err(ParseError::UnboxableType(
+                        self.source_code.clone(),
+                        type_token.span,
+                    ));
                 }
                 _ => {
+                    self.skip_box_name();
                     return Err(ParseError::BoxesNeedTypes(
                         self.source_code.clone(),
                         type_token.span,
-                    ))
+                    ));
                 }
             };
             if let Some(ident_token) = self.tokens.pop_front() {
+                let module = self.scopes.module_at(ident_token.span.offset());
                 Ok(OpKind::CreateBox {
-                    name: ident_token.inner,
+                    name: qualify(module, &ident_token.inner),
                     val_type: box_type,
                 })
             } else {
@@ -125,3 +423,35 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, parser::Parser};
+    use expect_test::expect;
+    use miette::Diagnostic;
+
+    #[test]
+    fn test_recovers_after_errors() {
+        let source = "box 1 x 2 box type::box<int> y 3 box type::str";
+        let tokens = Lexer::new(source).lex().unwrap();
+        let (ops, errors) = Parser::new(tokens, source.to_string()).parse_recovering();
+
+        let actual: Vec<String> = errors
+            .iter()
+            .map(|e| e.code().unwrap().to_string())
+            .chain(ops.iter().map(|op| format!("{:?}", op.kind)))
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "roth::boxes_need_types",
+                "roth::unboxable_type",
+                "roth::boxes_need_names",
+                "PushInt { val: 2 }",
+                "PushInt { val: 3 }",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -1,16 +1,237 @@
 use fancy_regex::Regex;
-use miette::Result;
-use std::{fs::File, io::Read, path::PathBuf};
+use miette::{Report, Result, SourceSpan};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
-use crate::error::ParseError;
+use crate::{
+    capabilities::Capabilities,
+    checker::Checker,
+    error::{Errors, ParseError},
+    lexer::Lexer,
+    modules::{module_key, qualify, Resolved, Scopes},
+    op::OpKind,
+    parser::Parser,
+    val::ValType,
+};
 
-static INCLUDE_REGEX: &str = r"include\s*\((?<file>.+?(?=\)))\)";
-static MACRO_DEF_REGEX: &str = r"macro\s+(.+?)(?=\s)((.|\n|\r|\t)+?)(?=end)";
+static INCLUDE_REGEX: &str = r"(?<kind>include|import)\s*\((?<file>.+?(?=\)))\)";
+// `as <name>` right after an import
+static ALIAS_REGEX: &str = r"^[ \t]+as[ \t]+(?<alias>[^\s]+)";
+
+// `( int int -- int )` on a macro definition
+#[derive(Debug, Clone)]
+pub struct StackEffect {
+    pub inputs: Vec<ValType>,
+    pub outputs: Vec<ValType>,
+    pub span: SourceSpan,
+}
+
+impl std::fmt::Display for StackEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = |t: &ValType| t.to_string().replace("type::", "");
+
+        let mut words = vec![String::from("(")];
+        words.extend(self.inputs.iter().map(name));
+        words.push(String::from("--"));
+        words.extend(self.outputs.iter().map(name));
+        words.push(String::from(")"));
+
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+// Where a macro got expanded in the output
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    pub name: String,
+    pub effect: Option<StackEffect>,
+    pub span: SourceSpan,
+    // the name in the macro's definition
+    pub definition: SourceSpan,
+    // where the body in the macro's definition starts
+    pub body_start: usize,
+}
+
+impl Expansion {
+    fn shifted(&self, by: usize) -> Self {
+        Self {
+            span: (self.span.offset() + by, self.span.len()).into(),
+            ..self.clone()
+        }
+    }
+}
+
+// Where the contents of an included file ended up in the output
+#[derive(Debug, Clone)]
+pub struct Inclusion {
+    pub file: String,
+    pub span: SourceSpan,
+    // where each line of the file starts in the output, for lines that made it
+    // there as is (i.e. not from inside a macro body)
+    pub line_starts: Vec<(usize, usize)>,
+}
+
+impl Inclusion {
+    // 1-based line in the included file that `offset` in the output came from
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        self.line_starts
+            .iter()
+            .take_while(|(start, _)| *start <= offset)
+            .last()
+            .map(|(_, line)| *line)
+    }
+}
+
+// Which file and line the output came from. Macro bodies count as the place
+// they were used.
+#[derive(Debug, Clone, Default)]
+pub struct LineMap {
+    // every line start and everything that was copied as is, sorted by offset
+    marks: Vec<Mark>,
+    // what the program (`None`) and every file it includes say, for going from a
+    // line and column back to an offset in them
+    files: Vec<(Option<String>, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Mark {
+    // in the output
+    offset: usize,
+    // how much after `offset` was copied as is
+    len: usize,
+    // `None` for the program itself
+    file: Option<String>,
+    // 1-based
+    line: usize,
+    column: usize,
+}
+
+impl LineMap {
+    pub fn line_of(&self, offset: usize) -> Option<(Option<&str>, usize)> {
+        self.position_of(offset).map(|(file, line, _)| (file, line))
+    }
+
+    // (file, 1-based line, 1-based column)
+    pub fn position_of(&self, offset: usize) -> Option<(Option<&str>, usize, usize)> {
+        self.marks
+            .iter()
+            .take_while(|mark| mark.offset <= offset)
+            .last()
+            .map(|mark| {
+                let column = mark.column + (offset - mark.offset).min(mark.len);
+                (mark.file.as_deref(), mark.line, column)
+            })
+    }
+
+    // (file, byte offset in it) that `offset` in the output came from
+    pub fn offset_of(&self, offset: usize) -> Option<(Option<&str>, usize)> {
+        let (file, line, column) = self.position_of(offset)?;
+        let text = self.text_of(file)?;
+        let line_start = match line {
+            1 => 0,
+            _ => text.match_indices('\n').nth(line - 2)?.0 + 1,
+        };
+
+        Some((file, line_start + column - 1))
+    }
+
+    // What `file` (`None` for the program) says
+    pub fn text_of(&self, file: Option<&str>) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(f, _)| f.as_deref() == file)
+            .map(|(_, text)| text.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub name: String,
+    // already expanded, so using it never needs to look at other macros
+    pub body: String,
+    pub effect: Option<StackEffect>,
+    // the name in the definition
+    pub definition: SourceSpan,
+    // where the body starts in the definition (which is copied to the output as is)
+    body_start: usize,
+    // expansions inside of `body`, relative to the start of it
+    expansions: Vec<Expansion>,
+}
+
+impl Macro {
+    // This macro and everything inside of it, expanded at `at`
+    fn expanded_at(&self, at: usize) -> Vec<Expansion> {
+        let mut expansions = vec![Expansion {
+            name: self.name.clone(),
+            effect: self.effect.clone(),
+            span: (at, self.body.len()).into(),
+            definition: self.definition.clone(),
+            body_start: self.body_start,
+        }];
+        expansions.extend(self.expansions.iter().map(|e| e.shifted(at)));
+
+        expansions
+    }
+}
+
+// Where the contents of an included file are in `source_after_includes`
+#[derive(Debug, Clone)]
+struct Included {
+    // as it's written, next to the program if it was included from another file
+    file: String,
+    start: usize,
+    end: usize,
+    // 1 for files the program includes, 2 for the ones they include, ...
+    depth: usize,
+    // what's in the file
+    contents: String,
+    // what the module it's part of is called inside the preprocessor (`None`
+    // for the program)
+    module: Option<String>,
+}
+
+struct Definition {
+    name: String,
+    name_start: usize,
+    effect: Option<StackEffect>,
+    body_start: usize,
+    body_end: usize,
+    end: usize,
+}
 
 pub struct PreProcessor {
     source_raw: String,
     source_after_includes: String,
-    source_after_macros: String
+    source_after_macros: String,
+    macros: Vec<Macro>,
+    expansions: Vec<Expansion>,
+    // included files in `source_after_includes`, outer ones first, and then
+    // where they ended up in the output
+    included: Vec<Included>,
+    inclusions: Vec<Inclusion>,
+    lines: LineMap,
+    offset: usize,
+    // for the unused macro lint
+    definitions: Vec<(String, SourceSpan)>,
+    used: HashSet<String>,
+    // which files can be included
+    capabilities: Capabilities,
+    // where includes are looked for when they aren't next to the file including them
+    search_path: Vec<PathBuf>,
+    // where the program is, which its includes are relative to, and what to
+    // call it and where it really is if it's a file
+    dir: PathBuf,
+    program: Option<(String, PathBuf)>,
+    // where every included file really is, so each is only included once
+    included_files: HashSet<PathBuf>,
+    // what every module is called internally (see `module_key`)
+    module_keys: HashMap<PathBuf, String>,
+    // what modules import and export, for finding the macros they use. The
+    // parser works out the same for boxes.
+    scopes: Scopes,
 }
 
 impl PreProcessor {
@@ -19,95 +240,1081 @@ impl PreProcessor {
             source_raw: buffer.to_string(),
             source_after_includes: String::new(),
             source_after_macros: String::new(),
+            macros: Vec::new(),
+            expansions: Vec::new(),
+            included: Vec::new(),
+            inclusions: Vec::new(),
+            lines: LineMap::default(),
+            offset: 0,
+            definitions: Vec::new(),
+            used: HashSet::new(),
+            capabilities: Capabilities::default(),
+            search_path: search_path(&[]),
+            dir: PathBuf::new(),
+            program: None,
+            included_files: HashSet::new(),
+            module_keys: HashMap::new(),
+            scopes: Scopes::default(),
         }
     }
 
-    pub fn expand(mut self) -> Result<String> {
+    // Macros that were defined somewhere else (i.e. an earlier line in the repl)
+    // and should also be expanded in this buffer
+    pub fn with_macros(mut self, macros: Vec<Macro>) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    // Spans we hand out start counting from `offset`, for when the output gets
+    // appended to some bigger source (i.e. a repl session)
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    // Only include files `capabilities` allow
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    // Look for includes in `dirs` (i.e. from `-I`) before the ones in `ROTH_PATH`
+    pub fn with_include_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.search_path = search_path(&dirs);
+        self
+    }
+
+    // The program is the file at `path`, so what it includes is next to it, and
+    // including it again is a cycle
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.program = path.file_name().map(|name| {
+            let real_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            (name.to_string_lossy().to_string(), real_path)
+        });
+        self
+    }
+
+    pub fn macros(&self) -> &[Macro] {
+        &self.macros
+    }
+
+    pub fn macro_names(&self) -> Vec<String> {
+        self.macros.iter().map(|m| m.name.clone()).collect()
+    }
+
+    // What the modules import and export
+    pub fn scopes(&self) -> &Scopes {
+        &self.scopes
+    }
+
+    // Every expansion of a macro, outer ones first
+    pub fn expansions(&self) -> &[Expansion] {
+        &self.expansions
+    }
+
+    pub fn inclusions(&self) -> &[Inclusion] {
+        &self.inclusions
+    }
+
+    pub fn lines(&self) -> &LineMap {
+        &self.lines
+    }
+
+    // Names of the macros defined in this buffer that never got expanded, apart
+    // from the ones modules export for others to use
+    pub fn unused_macros(&self) -> Vec<(String, SourceSpan)> {
+        self.definitions
+            .iter()
+            .filter(|(name, _)| !self.used.contains(name) && !self.scopes.is_exported(name))
+            .cloned()
+            .collect()
+    }
+
+    pub fn expand(&mut self) -> Result<String> {
+        let (expanded, errors) = self.expand_recovering()?;
+        Errors::into_result(errors)?;
+
+        Ok(expanded)
+    }
+
+    // Like `expand`, but macros that break their stack effect are handed back
+    // alongside the output instead of stopping everything
+    pub fn expand_recovering(&mut self) -> Result<(String, Vec<Report>)> {
         self.expand_includes()?;
-        self.expand_macros()?;
+        let errors = self.expand_macros()?;
 
-        Ok(self.source_after_macros)
+        Ok((self.source_after_macros.clone(), errors))
     }
 
-    fn expand_macros(&mut self) -> Result<()> {
-        let mut expanded_file = self.source_after_includes.clone();
-    
-        let re = Regex::new(MACRO_DEF_REGEX).unwrap();
-        let mut start = 0;
-        while let Some(m) = re.captures_from_pos(&self.source_after_includes, start).unwrap() {
-            let full_match = m.get(0).unwrap().as_str();
-            let macro_name = m.get(1).unwrap().as_str();
-            let macro_body = m.get(2).unwrap().as_str();
+    // Macro definitions are left where they are (the parser skips over them), and
+    // every use of a macro after its definition is replaced by its body
+    fn expand_macros(&mut self) -> Result<Vec<Report>> {
+        let text = self.source_after_includes.clone();
+        let mut out = String::with_capacity(text.len());
+        let mut defined = Vec::new();
+        // (start in `text`, start in `out`, length) of everything copied as is
+        let mut copied = Vec::new();
+        let mut copy = |out: &mut String, from: usize, to: usize| {
+            copied.push((from, out.len(), to - from));
+            out.push_str(&text[from..to]);
+        };
+
+        let mut pos = 0;
+        while let Some((start, end)) = next_word(&text, pos) {
+            copy(&mut out, pos, start);
+            let word = &text[start..end];
+            let module = self.included_at(start).and_then(|i| i.module.clone());
+
+            // `module <name>` and `import("file") as <name>` are left for the
+            // parser, and what they name isn't a macro
+            let next = next_word(&text, end);
+            let name_end = match next {
+                Some((_, name_end)) if word == "module" => Some(name_end),
+                Some((as_start, as_end)) if word.starts_with("import(") => {
+                    match &text[as_start..as_end] {
+                        "as" => next_word(&text, as_end).map(|(_, name_end)| name_end),
+                        _ => Some(end),
+                    }
+                }
+                None if word.starts_with("import(") => Some(end),
+                _ => None,
+            };
+            if let Some(name_end) = name_end {
+                copy(&mut out, start, name_end);
+                pos = name_end;
+                continue;
+            }
 
-            //println!("Found match: {:?} - name = '{}', body = '{}'", full_match, macro_name, macro_body);
+            // the parser says so if it's anywhere it shouldn't be
+            if word == "export" {
+                if let (Some(_), Some(name)) = (&module, exported_name(&text, end)) {
+                    self.scopes.export(qualify(module.as_deref(), name));
+                }
+                copy(&mut out, start, end);
+                pos = end;
+                continue;
+            }
+
+            if word == "macro" {
+                let def = self.parse_definition(&text, start, end)?;
+                // so macros from different modules don't clash
+                let name = qualify(module.as_deref(), &def.name);
+
+                // the definition is copied as is, so spans inside it stay valid
+                let def_start = self.offset + out.len();
+                copy(&mut out, start, def.end);
+                let shift = |span: &SourceSpan| -> SourceSpan {
+                    (def_start + span.offset() - start, span.len()).into()
+                };
 
-            start = m.get(0).unwrap().start() + full_match.len() + 3; // adding 3 for the 'end' keyword
+                let definition = shift(&(def.name_start, def.name.len()).into());
+                self.definitions.push((name.clone(), definition.clone()));
 
-            // replace all occurrences AFTER THE START POSITION in the file with the macro body
-            let (front, rest) = expanded_file.split_at(start);
-            //println!("Front part: {}", front);
-            //println!("Rest part: {}", rest);
+                let raw_body = text[def.body_start..def.body_end].trim();
+                let body_offset = text[def.body_start..def.body_end]
+                    .find(raw_body)
+                    .unwrap_or(0);
+                let raw_start = def.body_start + body_offset;
+                let (body, expansions) = self.expand_body(
+                    &text,
+                    raw_start..raw_start + raw_body.len(),
+                    module.as_deref(),
+                )?;
+                let effect = def.effect.map(|effect| StackEffect {
+                    span: shift(&effect.span),
+                    ..effect
+                });
 
-            let expanded_rest = rest.replace(macro_name, macro_body);
+                let body_start = def_start + raw_start - start;
+                if effect.is_some() {
+                    defined.push((name.clone(), body_start, raw_body.to_string()));
+                }
+
+                self.macros.retain(|m| m.name != name);
+                self.macros.push(Macro {
+                    name,
+                    body,
+                    effect,
+                    definition,
+                    body_start,
+                    expansions,
+                });
+
+                pos = def.end;
+                continue;
+            }
 
-            expanded_file = format!("{}{}", front, expanded_rest);
+            let span = (start, end - start).into();
+            match self.macro_named(word, module.as_deref(), &text, span)? {
+                Some(idx) => {
+                    let m = &self.macros[idx];
+                    self.used.insert(m.name.clone());
+                    self.expansions
+                        .extend(m.expanded_at(self.offset + out.len()));
+                    out.push_str(&m.body);
+                }
+                None => copy(&mut out, start, end),
+            }
+            pos = end;
         }
+        copy(&mut out, pos, text.len());
 
-        self.source_after_macros = expanded_file;
-        Ok(())
+        self.inclusions = self
+            .included
+            .iter()
+            .map(|included| self.inclusion(&text, &copied, included))
+            .collect();
+        self.lines = self.line_map(&text, &copied);
+
+        // outer expansions before the ones nested inside of them
+        self.expansions
+            .sort_by_key(|e| (e.span.offset(), usize::MAX - e.span.len()));
+
+        let mut errors = Vec::new();
+        for (name, body_start, raw_body) in defined {
+            errors.extend(self.verify_effect(&out, &name, body_start, &raw_body));
+        }
+
+        self.source_after_macros = out;
+        Ok(errors)
     }
 
-    fn expand_includes(&mut self) -> Result<()> {
-        let mut expanded_file = String::from(&self.source_raw);
-        let re = Regex::new(INCLUDE_REGEX).unwrap();
-        let mut start = 0;
-        while let Some(m) = re.captures_from_pos(&self.source_raw, start).unwrap() {
-            let full_match = m.get(0).unwrap().as_str();
-            let raw_file_match = m.get(1).unwrap().as_str();
-
-            start = m.get(0).unwrap().start() + 1;
-
-            // trim first and last char since it matches with quotation marks
-            let mut trimmed = String::from(raw_file_match);
-            trimmed.pop();
-            trimmed.remove(0);
-
-            let file_path = PathBuf::from(trimmed.clone());
-            if !file_path.exists() {
-                return Err(ParseError::CantIncludeNonExistentFile(
-                    self.source_raw.clone(),
-                    trimmed.clone(),
-                    (start, full_match.len()).into(),
+    fn line_map(&self, text: &str, copied: &[(usize, usize, usize)]) -> LineMap {
+        let mut marks = Vec::new();
+        for (from, to, len) in copied {
+            let line_starts = std::iter::once(*from).chain(
+                (*from..from + len)
+                    .filter(|i| text.as_bytes()[*i] == b'\n')
+                    .map(|i| i + 1),
+            );
+            for offset in line_starts {
+                let (file, line, column) = self.origin(text, offset);
+                marks.push(Mark {
+                    offset: self.offset + to + offset - from,
+                    len: from + len - offset,
+                    file,
+                    line,
+                    column,
+                });
+            }
+        }
+
+        let files = std::iter::once((None, self.source_raw.clone()))
+            .chain(
+                self.included
+                    .iter()
+                    .map(|i| (Some(i.file.clone()), i.contents.clone())),
+            )
+            .collect();
+
+        LineMap { marks, files }
+    }
+
+    // The innermost included file `offset` in `text` is from
+    fn included_at(&self, offset: usize) -> Option<&Included> {
+        self.included
+            .iter()
+            .rev()
+            .find(|included| included.start <= offset && offset < included.end)
+    }
+
+    // The file, line and column of an offset in `text`
+    fn origin(&self, text: &str, offset: usize) -> (Option<String>, usize, usize) {
+        let included = self.included_at(offset);
+        let (line, column) = self.position_in(text, included, offset);
+
+        (included.map(|i| i.file.clone()), line, column)
+    }
+
+    // The line and column of an offset in `text` in `included` (`None` for the
+    // program), even if it's inside a file that one includes
+    fn position_in(
+        &self,
+        text: &str,
+        included: Option<&Included>,
+        offset: usize,
+    ) -> (usize, usize) {
+        let newlines = |from: usize, to: usize| text[from..to].matches('\n').count();
+        let (start, depth) = included.map_or((0, 0), |i| (i.start, i.depth));
+
+        // the lines of files it includes aren't its own
+        let from_includes: usize = self
+            .included
+            .iter()
+            .filter(|i| i.depth == depth + 1 && start <= i.start && i.end <= offset)
+            .map(|i| newlines(i.start, i.end))
+            .sum();
+        let column = match text[start..offset].rfind('\n') {
+            Some(idx) => offset - (start + idx),
+            None => offset - start + 1,
+        };
+
+        (1 + newlines(start, offset) - from_includes, column)
+    }
+
+    // Work out where an included file ended up in the output
+    fn inclusion(
+        &self,
+        text: &str,
+        copied: &[(usize, usize, usize)],
+        included: &Included,
+    ) -> Inclusion {
+        let (start, end) = (included.start, included.end);
+        // only offsets that were copied as is have a place in the output
+        let to_out = |offset: usize| {
+            copied
+                .iter()
+                .find(|(from, _, len)| *from <= offset && offset < from + len)
+                .map(|(from, to, _)| self.offset + to + offset - from)
+        };
+        // a line that starts with an include is still one of this file's
+        let in_include = |offset: usize| {
+            self.included.iter().any(|i| {
+                i.depth > included.depth && start <= i.start && i.start < offset && offset < i.end
+            })
+        };
+
+        let line_starts = std::iter::once(start)
+            .chain(
+                (start..end)
+                    .filter(|i| text.as_bytes()[*i] == b'\n')
+                    .map(|i| i + 1),
+            )
+            .filter(|offset| *offset < end && !in_include(*offset))
+            .filter_map(|offset| {
+                let (line, _) = self.position_in(text, Some(included), offset);
+                to_out(offset).map(|out| (out, line))
+            })
+            .collect();
+
+        let out_start = to_out(start).unwrap_or(self.offset);
+        let out_end = copied
+            .iter()
+            .filter(|(from, _, _)| *from < end)
+            .map(|(from, to, len)| self.offset + to + (end.min(from + len) - from))
+            .max()
+            .unwrap_or(out_start);
+
+        Inclusion {
+            file: included.file.clone(),
+            span: (out_start, out_end.saturating_sub(out_start)).into(),
+            line_starts,
+        }
+    }
+
+    // The macro `word`, written in `module`, stands for. Names that aren't
+    // macros are left for the parser, which knows about boxes.
+    fn macro_named(
+        &self,
+        word: &str,
+        module: Option<&str>,
+        text: &str,
+        span: SourceSpan,
+    ) -> Result<Option<usize>> {
+        let position = |name: &str| self.macros.iter().position(|m| m.name == name);
+        match self.scopes.resolve(word, module) {
+            Resolved::Name(name) => Ok(position(&name)),
+            Resolved::NotExported(name) if position(&name).is_some() => {
+                Err(self.scopes.error(word, module, text, span).into())
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Expand the macro body at `range` in `text`, written in `module`, with the
+    // macros defined so far
+    fn expand_body(
+        &mut self,
+        text: &str,
+        range: Range<usize>,
+        module: Option<&str>,
+    ) -> Result<(String, Vec<Expansion>)> {
+        let raw_body = &text[range.clone()];
+        let mut body = String::with_capacity(raw_body.len());
+        let mut expansions = Vec::new();
+
+        let mut pos = 0;
+        while let Some((start, end)) = next_word(raw_body, pos) {
+            body.push_str(&raw_body[pos..start]);
+            let word = &raw_body[start..end];
+
+            let span = (range.start + start, end - start).into();
+            match self.macro_named(word, module, text, span)? {
+                Some(idx) => {
+                    let m = &self.macros[idx];
+                    self.used.insert(m.name.clone());
+                    expansions.extend(m.expanded_at(body.len()));
+                    body.push_str(&m.body);
+                }
+                None => body.push_str(word),
+            }
+            pos = end;
+        }
+        body.push_str(&raw_body[pos..]);
+
+        Ok((body, expansions))
+    }
+
+    // `macro` has already been read, `keyword_start..keyword_end` is where it is
+    //  macro <name> [( <types> -- <types> )] <body> end
+    fn parse_definition(
+        &self,
+        text: &str,
+        keyword_start: usize,
+        keyword_end: usize,
+    ) -> Result<Definition> {
+        let (name_start, name_end) = match next_word(text, keyword_end) {
+            Some(name) => name,
+            None => {
+                return Err(ParseError::MacrosNeedNames(
+                    text.to_string(),
+                    (keyword_start, keyword_end - keyword_start).into(),
                 )
-                .into());
+                .into())
+            }
+        };
+        let name = text[name_start..name_end].to_string();
+
+        let mut body_start = name_end;
+        let mut effect = None;
+        if let Some((start, _)) = next_word(text, name_end) {
+            if text[start..].starts_with('(') {
+                let (parsed, end) = parse_effect(text, start)?;
+                effect = Some(parsed);
+                body_start = end;
+            }
+        }
+
+        // the body runs until the `end` that isn't closing an `if` or `until` inside of it
+        let mut depth = 0;
+        let mut pos = body_start;
+        while let Some((start, end)) = next_word(text, pos) {
+            match &text[start..end] {
+                "if" | "until" => depth += 1,
+                "end" if depth == 0 => {
+                    return Ok(Definition {
+                        name,
+                        name_start,
+                        effect,
+                        body_start,
+                        body_end: start,
+                        end,
+                    })
+                }
+                "end" => depth -= 1,
+                _ => {}
             }
+            pos = end;
+        }
+
+        Err(ParseError::UnclosedMacro(
+            text.to_string(),
+            (keyword_start, keyword_end - keyword_start).into(),
+        )
+        .into())
+    }
 
-            match File::open(&trimmed) {
-                Ok(mut file) => {
-                    let mut file_contents = String::new();
-                    if file.read_to_string(&mut file_contents).is_err() {
-                        return Err(ParseError::CantOpenOrReadIncludeFile(
-                            self.source_raw.clone(),
-                            trimmed.clone(),
-                            (start, full_match.len()).into(),
-                        )
-                        .into());
+    // Make sure the body of a macro does what its stack effect says, as far as the
+    // checker can tell. Bodies using macros without a stack effect can't be checked.
+    fn verify_effect(
+        &self,
+        out: &str,
+        name: &str,
+        body_start: usize,
+        raw_body: &str,
+    ) -> Vec<Report> {
+        let m = match self.macros.iter().find(|m| m.name == name) {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        let effect = match &m.effect {
+            Some(effect) => effect,
+            None => return Vec::new(),
+        };
+
+        // diagnostics are about `out`, which starts at `self.offset`
+        let local =
+            |span: &SourceSpan| -> SourceSpan { (span.offset() - self.offset, span.len()).into() };
+
+        let tokens = match Lexer::new(raw_body)
+            .with_offset(body_start - self.offset)
+            .lex()
+        {
+            Ok(tokens) => tokens,
+            Err(e) => return vec![e],
+        };
+        // the whole body is in the macro's module
+        let module = name.split_once("::").map(|(module, _)| module.to_string());
+        let scopes = self
+            .scopes
+            .clone()
+            .with_files(vec![(0, usize::MAX, module)]);
+        let ops = match Parser::new(tokens, out.to_string())
+            .with_scopes(scopes)
+            .parse()
+        {
+            Ok(ops) => ops,
+            Err(e) => return vec![e],
+        };
+
+        let mut effects = HashMap::new();
+        for op in &ops {
+            if let OpKind::PushBox { name } = &op.kind {
+                match self.macros.iter().find(|m| &m.name == name) {
+                    Some(Macro {
+                        effect: Some(effect),
+                        ..
+                    }) => {
+                        effects.insert(name.clone(), effect.clone());
                     }
+                    Some(_) => return Vec::new(),
+                    None => {}
+                }
+            }
+        }
+
+        let local_effect = StackEffect {
+            span: local(&effect.span),
+            ..effect.clone()
+        };
+        Checker::new(out.to_string(), ops)
+            .with_effect(local_effect)
+            .with_macro_effects(effects)
+            .check()
+    }
 
-                    expanded_file = expanded_file.replace(full_match, file_contents.as_str());
+    // Every file is included once, the first time it comes up, along with
+    // everything it includes
+    fn expand_includes(&mut self) -> Result<()> {
+        let mut expanded = String::with_capacity(self.source_raw.len());
+        let source = self.source_raw.clone();
+        let mut including: Vec<(String, PathBuf)> = self.program.iter().cloned().collect();
+        self.included_files
+            .extend(including.iter().map(|(_, path)| path.clone()));
+        self.include_into(&mut expanded, &source, None, None, &mut including)?;
+
+        self.source_after_includes = expanded;
+        Ok(())
+    }
+
+    // Copy `text` to `out` with its includes replaced by the files they point at.
+    // `text` is from `file` (`None` for the program), which is part of `module`,
+    // and `including` is every file it's included from (outermost first) as
+    // (file, where it really is), for finding cycles.
+    fn include_into(
+        &mut self,
+        out: &mut String,
+        text: &str,
+        file: Option<&str>,
+        module: Option<&str>,
+        including: &mut Vec<(String, PathBuf)>,
+    ) -> Result<()> {
+        let re = Regex::new(INCLUDE_REGEX).unwrap();
+        let alias_re = Regex::new(ALIAS_REGEX).unwrap();
+        let mut start = 0;
+        // end of the last include in `text`
+        let mut copied_up_to = 0;
+        while let Some(m) = re.captures_from_pos(text, start).unwrap() {
+            let whole = m.get(0).unwrap();
+            let importing = m.name("kind").unwrap().as_str() == "import";
+            let alias = match importing {
+                true => alias_re.captures(&text[whole.end()..]).unwrap(),
+                false => None,
+            };
+            let end = whole.end() + alias.as_ref().map_or(0, |a| a.get(0).unwrap().end());
+            let alias = alias.map(|a| a.name("alias").unwrap().as_str().to_string());
+            let span: SourceSpan = (whole.start(), end - whole.start()).into();
+            // trim the quotation marks
+            let written = m.name("file").unwrap().as_str().trim_matches('"');
+            start = end;
+
+            let found = locate_include(written, file, &self.dir, &self.search_path);
+            let (included, path) = match found {
+                Some(found) => found,
+                None => {
+                    return Err(ParseError::CantIncludeNonExistentFile(
+                        text.to_string(),
+                        written.to_string(),
+                        span,
+                    )
+                    .into())
                 }
+            };
+            if !self.capabilities.can_include(&path) {
+                return Err(ParseError::IncludeNotAllowed(
+                    text.to_string(),
+                    written.to_string(),
+                    self.capabilities.include_help(),
+                    span,
+                )
+                .into());
+            }
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(contents) => contents,
                 Err(_) => {
                     return Err(ParseError::CantOpenOrReadIncludeFile(
-                        self.source_raw.clone(),
-                        trimmed.clone(),
-                        (start, full_match.len()).into(),
+                        text.to_string(),
+                        written.to_string(),
+                        span,
                     )
                     .into())
                 }
+            };
+
+            let real_path = path.canonicalize().unwrap_or(path);
+            if including.iter().any(|(_, p)| *p == real_path) {
+                let cycle: Vec<&str> = including
+                    .iter()
+                    .map(|(f, _)| f.as_str())
+                    .chain([included.as_str()])
+                    .collect();
+                return Err(
+                    ParseError::IncludeCycle(text.to_string(), cycle.join(" -> "), span).into(),
+                );
             }
+
+            // a file starting with `module <name>` is a module of its own, which
+            // the file bringing it in knows by its name (or the alias it gave it).
+            // Other files are part of the module that includes them.
+            let declared = declared_module(&contents);
+            let included_module = match declared {
+                Some(name) => {
+                    let key = self.module_key(&real_path, name);
+                    self.scopes
+                        .import(module, alias.as_deref().unwrap_or(name), &key);
+                    Some(key)
+                }
+                None => module.map(String::from),
+            };
+
+            out.push_str(&text[copied_up_to..whole.start()]);
+            copied_up_to = end;
+            // the parser works out what's imported from the statement, with the
+            // file called what its contents are called in `inclusions`
+            if importing || declared.is_some() {
+                out.push_str(&format!("import(\"{}\")", included));
+                if let Some(alias) = &alias {
+                    out.push_str(&format!(" as {}", alias));
+                }
+                out.push(' ');
+            }
+            if !self.included_files.insert(real_path.clone()) {
+                continue;
+            }
+
+            let idx = self.included.len();
+            self.included.push(Included {
+                file: included.clone(),
+                start: out.len(),
+                end: out.len(),
+                // the program is in `including` too when it's a file
+                depth: match self.program {
+                    Some(_) => including.len(),
+                    None => including.len() + 1,
+                },
+                contents: contents.clone(),
+                module: included_module.clone(),
+            });
+            including.push((included.clone(), real_path));
+            self.include_into(
+                out,
+                &contents,
+                Some(&included),
+                included_module.as_deref(),
+                including,
+            )?;
+            including.pop();
+            self.included[idx].end = out.len();
         }
 
-        self.source_after_includes = expanded_file;
+        out.push_str(&text[copied_up_to..]);
         Ok(())
     }
+
+    // What the module `name` at `path` is called internally, which stays the
+    // same however many times it's imported
+    fn module_key(&mut self, path: &Path, name: &str) -> String {
+        if let Some(key) = self.module_keys.get(path) {
+            return key.clone();
+        }
+
+        let taken: Vec<String> = self.module_keys.values().cloned().collect();
+        let key = module_key(name, &taken);
+        self.module_keys.insert(path.to_path_buf(), key.clone());
+
+        key
+    }
+}
+
+// Where includes that aren't next to the file including them are looked for:
+// `include_dirs`, and then the directories in `ROTH_PATH`
+pub fn search_path(include_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let roth_path = std::env::var_os("ROTH_PATH").unwrap_or_default();
+    include_dirs
+        .iter()
+        .cloned()
+        .chain(std::env::split_paths(&roth_path))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect()
+}
+
+// The file `include("<written>")` in `including` (`None` for the program)
+// points at, as (what to call it, where it is). It's looked for next to the
+// file including it, and then in `search_path`. Files are called what the
+// program, which is in `dir`, would include them as.
+pub fn locate_include(
+    written: &str,
+    including: Option<&str>,
+    dir: &Path,
+    search_path: &[PathBuf],
+) -> Option<(String, PathBuf)> {
+    let name = match including.and_then(|file| Path::new(file).parent()) {
+        Some(including_dir) => including_dir.join(written.trim_start_matches("./")),
+        None => PathBuf::from(written),
+    };
+    if dir.join(&name).is_file() {
+        return Some((name.display().to_string(), dir.join(&name)));
+    }
+
+    search_path
+        .iter()
+        .map(|search_dir| search_dir.join(written))
+        .find(|path| path.is_file())
+        .map(|path| (path.display().to_string(), path))
+}
+
+// Every file the program in `dir` includes, along with the ones those include,
+// as (what to call it, contents, the file that included it). Files that can't be
+// read or that `capabilities` don't allow (which the preprocessor reports) are
+// left out.
+pub fn included_files(
+    source: &str,
+    dir: &Path,
+    search_path: &[PathBuf],
+    capabilities: &Capabilities,
+) -> Vec<(String, String, Option<String>)> {
+    fn walk(
+        text: &str,
+        file: Option<&str>,
+        dir: &Path,
+        search_path: &[PathBuf],
+        capabilities: &Capabilities,
+        seen: &mut HashSet<PathBuf>,
+        files: &mut Vec<(String, String, Option<String>)>,
+    ) {
+        for (written, _) in find_includes(text) {
+            let (name, path) = match locate_include(&written, file, dir, search_path) {
+                Some(found) if capabilities.can_include(&found.1) => found,
+                _ => continue,
+            };
+            // like the preprocessor, every file is only included once
+            if !seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+                continue;
+            }
+            if let Ok(contents) = std::fs::read_to_string(&path) {
+                files.push((name.clone(), contents.clone(), file.map(String::from)));
+                walk(
+                    &contents,
+                    Some(&name),
+                    dir,
+                    search_path,
+                    capabilities,
+                    seen,
+                    files,
+                );
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(
+        source,
+        None,
+        dir,
+        search_path,
+        capabilities,
+        &mut HashSet::new(),
+        &mut files,
+    );
+    files
+}
+
+// Where the text at `offset` in the output was written, following macro bodies
+// back to their definitions
+pub fn origin_of(expansions: &[Expansion], offset: usize) -> usize {
+    let within = |outer: &Expansion, offset: usize| {
+        outer.span.offset() <= offset && offset < outer.span.offset() + outer.span.len()
+    };
+
+    // outer expansions come first, so the last one is the innermost
+    let idx = match expansions.iter().rposition(|e| within(e, offset)) {
+        Some(idx) => idx,
+        None => return offset,
+    };
+    let inner = &expansions[idx];
+
+    // macros used in the body before `offset` made it longer than it's written
+    let mut used: Vec<&Expansion> = Vec::new();
+    for e in &expansions[idx + 1..] {
+        let end = e.span.offset() + e.span.len();
+        if !within(inner, e.span.offset()) || end > offset {
+            continue;
+        }
+        // only the ones written in this body, not the ones in their bodies
+        if used
+            .last()
+            .is_some_and(|last| within(last, e.span.offset()))
+        {
+            continue;
+        }
+        used.push(e);
+    }
+    let written: usize = used.iter().map(|e| e.name.len()).sum();
+    let expanded: usize = used.iter().map(|e| e.span.len()).sum();
+
+    inner.body_start + (offset - inner.span.offset()) + written - expanded
+}
+
+// Every `include("file")` and `import("file")` in `source`, with the file name
+// and where the whole
+// include is
+pub fn find_includes(source: &str) -> Vec<(String, SourceSpan)> {
+    let re = Regex::new(INCLUDE_REGEX).unwrap();
+    let mut includes = Vec::new();
+    let mut start = 0;
+    while let Ok(Some(m)) = re.captures_from_pos(source, start) {
+        let whole = m.get(0).unwrap();
+        let file = m.name("file").unwrap().as_str().trim_matches('"');
+        includes.push((
+            file.to_string(),
+            (whole.start(), whole.end() - whole.start()).into(),
+        ));
+        start = whole.end();
+    }
+
+    includes
+}
+
+// The name a module declares with `module <name>` at the start of `text`
+fn declared_module(text: &str) -> Option<&str> {
+    let (start, end) = next_word(text, 0)?;
+    if &text[start..end] != "module" {
+        return None;
+    }
+
+    next_word(text, end).map(|(start, end)| &text[start..end])
+}
+
+// The name of the macro or box an `export` that ends at `pos` is in front of
+fn exported_name(text: &str, pos: usize) -> Option<&str> {
+    let (start, end) = next_word(text, pos)?;
+    // `box <type> <name>`
+    let (start, end) = match &text[start..end] {
+        "macro" => next_word(text, end)?,
+        "box" => next_word(text, next_word(text, end)?.1)?,
+        _ => return None,
+    };
+
+    Some(&text[start..end])
+}
+
+// Find the next word at or after `pos`, skipping whitespace and comments.
+// Strings count as a single word, even if they have spaces in them.
+fn next_word(text: &str, mut pos: usize) -> Option<(usize, usize)> {
+    loop {
+        let rest = &text[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+
+        if trimmed.is_empty() {
+            return None;
+        }
+
+        if trimmed.starts_with("//") {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            continue;
+        }
+
+        let mut len = 0;
+        if let Some(rest) = trimmed.strip_prefix('"') {
+            len = rest.find('"').map_or(trimmed.len(), |idx| idx + 2);
+        }
+        len += trimmed[len..]
+            .find(char::is_whitespace)
+            .unwrap_or(trimmed.len() - len);
+
+        return Some((pos, pos + len));
+    }
+}
+
+// Parse `( int int -- int )` starting at `start`, returning it and where it ends
+fn parse_effect(text: &str, start: usize) -> Result<(StackEffect, usize)> {
+    let end = match text[start..].find(')') {
+        Some(idx) => start + idx + 1,
+        None => {
+            return Err(
+                ParseError::MalformedStackEffect(text.to_string(), (start, 1).into()).into(),
+            )
+        }
+    };
+    let span: SourceSpan = (start, end - start).into();
+
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    let mut seen_separator = false;
+
+    let mut pos = start + 1;
+    while let Some((word_start, word_end)) = next_word(&text[..end - 1], pos) {
+        let word = &text[word_start..word_end];
+        if word == "--" {
+            if seen_separator {
+                return Err(ParseError::MalformedStackEffect(text.to_string(), span).into());
+            }
+            seen_separator = true;
+        } else {
+            match ValType::from_name(word) {
+                Some(val_type) if seen_separator => outputs.push(val_type),
+                Some(val_type) => inputs.push(val_type),
+                None => {
+                    return Err(ParseError::UnknownStackEffectType(
+                        text.to_string(),
+                        word.to_string(),
+                        (word_start, word_end - word_start).into(),
+                    )
+                    .into())
+                }
+            }
+        }
+        pos = word_end;
+    }
+
+    if !seen_separator {
+        return Err(ParseError::MalformedStackEffect(text.to_string(), span).into());
+    }
+
+    Ok((
+        StackEffect {
+            inputs,
+            outputs,
+            span,
+        },
+        end,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::preprocessor::PreProcessor;
+    use expect_test::expect;
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("roth-includes-{}", std::process::id()));
+        let files = [
+            (
+                "lib/math.roth",
+                "include(\"stack.roth\")\nmacro sq dup * end\n",
+            ),
+            (
+                "lib/stack.roth",
+                "include(\"../shared.roth\")\nmacro nip swap drop end\n",
+            ),
+            ("shared.roth", "macro two 2 end\n"),
+            ("vendor/extra.roth", "macro three 3 end\n"),
+            ("cycle/a.roth", "include(\"b.roth\")\n"),
+            ("cycle/b.roth", "1\ninclude(\"a.roth\")\n"),
+            ("root/main.roth", "include(\"lib.roth\")\n"),
+            ("root/lib.roth", "include(\"main.roth\")\n"),
+            ("root/app.roth", "include(\"helper.roth\")\n1\n2 two\n"),
+            ("root/helper.roth", "// helper\nmacro two 2 end\n"),
+        ];
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let include = |file: &str| format!("include(\"{}\")", dir.join(file).display());
+
+        let program = format!(
+            "{}\n{}\ninclude(\"extra.roth\")\ntwo sq",
+            include("lib/math.roth"),
+            include("shared.roth"),
+        );
+        let mut preprocessor =
+            PreProcessor::new(&program).with_include_dirs(vec![dir.join("vendor")]);
+        let expanded = preprocessor.expand().unwrap();
+        // where every line of the output came from
+        let mut actual: Vec<String> = std::iter::once(0)
+            .chain(expanded.match_indices('\n').map(|(idx, _)| idx + 1))
+            .filter(|offset| *offset < expanded.len())
+            .map(|offset| {
+                let (file, line) = preprocessor.lines().line_of(offset).unwrap();
+                let text = expanded[offset..].lines().next().unwrap_or_default();
+                format!("{}:{}  {}", file.unwrap_or("<program>"), line, text)
+            })
+            .collect();
+
+        let cycle = PreProcessor::new(&include("cycle/a.roth")).expand();
+        actual.push(cycle.unwrap_err().to_string());
+        // includes are next to the program, wherever we're running from
+        let main = dir.join("root/main.roth");
+        let cycle = PreProcessor::new(&fs::read_to_string(&main).unwrap())
+            .with_path(&main)
+            .expand();
+        actual.push(cycle.unwrap_err().to_string());
+
+        // and its lines are its own, past the files it includes
+        let app = dir.join("root/app.roth");
+        let mut preprocessor =
+            PreProcessor::new(&fs::read_to_string(&app).unwrap()).with_path(&app);
+        let expanded = preprocessor.expand().unwrap();
+        for (offset, _) in expanded.match_indices(char::is_numeric) {
+            let (file, line) = preprocessor.lines().line_of(offset).unwrap();
+            actual.push(format!("{}:{}", file.unwrap_or("<program>"), line));
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+        let actual = format!("{:#?}", actual).replace(&dir.display().to_string(), "<tmp>");
+        let expected = expect![[r#"
+            [
+                "<tmp>/lib/../shared.roth:1  macro two 2 end",
+                "<tmp>/lib/stack.roth:1  ",
+                "<tmp>/lib/stack.roth:2  macro nip swap drop end",
+                "<tmp>/lib/math.roth:1  ",
+                "<tmp>/lib/math.roth:2  macro sq dup * end",
+                "<program>:1  ",
+                "<program>:2  ",
+                "<tmp>/vendor/extra.roth:1  macro three 3 end",
+                "<program>:3  ",
+                "<program>:4  2 dup *",
+                "Files include each other: <tmp>/cycle/a.roth -> <tmp>/cycle/b.roth -> <tmp>/cycle/a.roth",
+                "Files include each other: main.roth -> lib.roth -> main.roth",
+                "helper.roth:2",
+                "<program>:2",
+                "<program>:3",
+                "<program>:3",
+            ]"#]];
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_macro_words() {
+        // only whole words are macros, and macros from an earlier buffer still apply
+        let mut first = PreProcessor::new("macro add + end\n1 2 add");
+        let first_expanded = first.expand().unwrap();
+        let mut second = PreProcessor::new("macro a 1 end\naddress add2 a add\n\"add\"")
+            .with_macros(first.macros().to_vec());
+        let second_expanded = second.expand().unwrap();
+
+        let expected = expect![[r#"
+            (
+                "macro add + end\n1 2 +",
+                "macro a 1 end\naddress add2 1 +\n\"add\"",
+            )
+        "#]];
+        expected.assert_debug_eq(&(first_expanded, second_expanded));
+    }
 }
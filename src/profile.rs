@@ -0,0 +1,258 @@
+use std::{collections::HashMap, fmt::Write as _, path::PathBuf, time::Duration};
+
+use miette::SourceSpan;
+
+use crate::preprocessor::{Expansion, LineMap};
+
+// How many rows each table in the report has
+const REPORT_ROWS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileOutput {
+    // `--profile`, a table of hot spots on stderr
+    Report,
+    // `--profile=<file>`, folded stacks for flamegraph tools
+    Folded(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    count: usize,
+    time: Duration,
+}
+
+impl Sample {
+    fn add(&mut self, other: Sample) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+// Counts how often every op runs and how long it takes, so we can say which
+// lines, macros and words a program spends its time in
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    // what to call the program itself
+    name: String,
+    lines: LineMap,
+    expansions: Vec<Expansion>,
+    // by (offset, len) of the op's span, since loops run the same op again
+    samples: HashMap<(usize, usize), Sample>,
+}
+
+impl Profiler {
+    pub fn new(name: &str, lines: LineMap, expansions: Vec<Expansion>) -> Self {
+        Self {
+            name: name.to_string(),
+            lines,
+            expansions,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, span: &SourceSpan, time: Duration) {
+        self.samples
+            .entry((span.offset(), span.len()))
+            .or_default()
+            .add(Sample { count: 1, time });
+    }
+
+    // The hottest locations, macros and words, slowest first. Time spent in a
+    // macro includes the macros it uses.
+    pub fn report(&self, source: &str) -> String {
+        let mut locations: HashMap<String, Sample> = HashMap::new();
+        let mut macros: HashMap<String, Sample> = HashMap::new();
+        let mut words: HashMap<String, Sample> = HashMap::new();
+        for (&(offset, len), sample) in &self.samples {
+            let word = text(source, offset, len);
+            locations
+                .entry(format!("{}  {}", self.location(offset), word))
+                .or_default()
+                .add(*sample);
+            for name in self.macros_at(offset) {
+                macros.entry(name.to_string()).or_default().add(*sample);
+            }
+            words.entry(word).or_default().add(*sample);
+        }
+
+        let total = self
+            .samples
+            .values()
+            .fold(Duration::ZERO, |total, sample| total + sample.time);
+        let mut report = String::new();
+        for (title, table) in [("location", locations), ("macro", macros), ("word", words)] {
+            if table.is_empty() {
+                continue;
+            }
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>6}  {:>8}  {}",
+                "time", "%", "count", title
+            );
+            let mut rows: Vec<(String, Sample)> = table.into_iter().collect();
+            // ties (and runs too fast to time) go by count, then name
+            rows.sort_by(|(a_name, a), (b_name, b)| {
+                (b.time, b.count, a_name).cmp(&(a.time, a.count, b_name))
+            });
+            for (name, sample) in rows.iter().take(REPORT_ROWS) {
+                let percent = match total.as_nanos() {
+                    0 => 0.0,
+                    total => sample.time.as_nanos() as f64 * 100.0 / total as f64,
+                };
+                let _ = writeln!(
+                    report,
+                    "{:>10}  {:>5.1}%  {:>8}  {}",
+                    format!("{:.2?}", sample.time),
+                    percent,
+                    sample.count,
+                    name
+                );
+            }
+            report.push('\n');
+        }
+
+        report
+    }
+
+    // One `program;outer macro;inner macro;op nanoseconds` line per stack, which
+    // is what `flamegraph.pl` and `inferno` read
+    pub fn folded(&self, source: &str) -> String {
+        let mut stacks: HashMap<String, u128> = HashMap::new();
+        for (&(offset, len), sample) in &self.samples {
+            let mut frames = vec![self.name.clone()];
+            frames.extend(self.macros_at(offset).iter().rev().map(|m| m.to_string()));
+            // `;` separates frames, and a space separates the count
+            frames.push(text(source, offset, len).replace([';', ' '], "_"));
+            *stacks.entry(frames.join(";")).or_default() += sample.time.as_nanos();
+        }
+
+        let mut lines: Vec<String> = stacks
+            .into_iter()
+            .map(|(stack, time)| format!("{} {}", stack, time))
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    fn location(&self, offset: usize) -> String {
+        match self.lines.position_of(offset) {
+            Some((file, line, column)) => {
+                format!("{}:{}:{}", file.unwrap_or(&self.name), line, column)
+            }
+            None => self.name.clone(),
+        }
+    }
+
+    // Innermost first
+    fn macros_at(&self, offset: usize) -> Vec<&str> {
+        self.expansions
+            .iter()
+            .rev()
+            .filter(|e| e.span.offset() <= offset && offset < e.span.offset() + e.span.len())
+            .map(|e| e.name.as_str())
+            .collect()
+    }
+}
+
+// Spans count chars
+fn text(source: &str, offset: usize, len: usize) -> String {
+    source.chars().skip(offset).take(len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        lexer::Lexer, parser::Parser, preprocessor::PreProcessor, profile::Profiler,
+        runtime::Runtime,
+    };
+    use expect_test::expect;
+
+    #[test]
+    fn test_profile() {
+        let mut preprocessor = PreProcessor::new(
+            "macro sq dup * end\nmacro quad sq sq end\nbox type::int i\nfalse until\n  i unpack 1 + i pack\n  2 quad drop\n  i unpack 3 eq\nend",
+        );
+        let source = preprocessor.expand().unwrap();
+        let tokens = Lexer::new(&source).lex().unwrap();
+        let ops = Parser::new(tokens, source.clone()).parse().unwrap();
+        let profiler = Profiler::new(
+            "p.roth",
+            preprocessor.lines().clone(),
+            preprocessor.expansions().to_vec(),
+        );
+        let mut runtime = Runtime::new(source.clone(), ops).with_profiler(profiler);
+        runtime.run().unwrap();
+
+        // real timings change every run, so every op takes a microsecond here
+        let mut profiler = runtime.profiler().unwrap().clone();
+        for sample in profiler.samples.values_mut() {
+            sample.time = Duration::from_micros(sample.count as u64);
+        }
+
+        let expected = expect![[r#"
+                  time       %     count  location
+                6.00µs   10.5%         6  p.roth:6:5  *
+                6.00µs   10.5%         6  p.roth:6:5  dup
+                4.00µs    7.0%         4  p.roth:4:7  until
+                3.00µs    5.3%         3  p.roth:5:12  1
+                3.00µs    5.3%         3  p.roth:5:14  +
+                3.00µs    5.3%         3  p.roth:5:16  i
+                3.00µs    5.3%         3  p.roth:5:18  pack
+                3.00µs    5.3%         3  p.roth:5:3  i
+                3.00µs    5.3%         3  p.roth:5:5  unpack
+                3.00µs    5.3%         3  p.roth:6:10  drop
+                3.00µs    5.3%         3  p.roth:6:3  2
+                3.00µs    5.3%         3  p.roth:7:12  3
+                3.00µs    5.3%         3  p.roth:7:14  eq
+                3.00µs    5.3%         3  p.roth:7:3  i
+                3.00µs    5.3%         3  p.roth:7:5  unpack
+                3.00µs    5.3%         3  p.roth:8:1  end
+                1.00µs    1.8%         1  p.roth:3:1  box
+                1.00µs    1.8%         1  p.roth:4:1  false
+
+                  time       %     count  macro
+               12.00µs   21.1%        12  quad
+               12.00µs   21.1%        12  sq
+
+                  time       %     count  word
+                9.00µs   15.8%         9  i
+                6.00µs   10.5%         6  *
+                6.00µs   10.5%         6  dup
+                6.00µs   10.5%         6  unpack
+                4.00µs    7.0%         4  until
+                3.00µs    5.3%         3  +
+                3.00µs    5.3%         3  1
+                3.00µs    5.3%         3  2
+                3.00µs    5.3%         3  3
+                3.00µs    5.3%         3  drop
+                3.00µs    5.3%         3  end
+                3.00µs    5.3%         3  eq
+                3.00µs    5.3%         3  pack
+                1.00µs    1.8%         1  box
+                1.00µs    1.8%         1  false
+
+            p.roth;+ 3000
+            p.roth;1 3000
+            p.roth;2 3000
+            p.roth;3 3000
+            p.roth;box 1000
+            p.roth;drop 3000
+            p.roth;end 3000
+            p.roth;eq 3000
+            p.roth;false 1000
+            p.roth;i 9000
+            p.roth;pack 3000
+            p.roth;quad;sq;* 6000
+            p.roth;quad;sq;dup 6000
+            p.roth;unpack 6000
+            p.roth;until 4000
+        "#]];
+        expected.assert_eq(&format!(
+            "{}{}",
+            profiler.report(&source),
+            profiler.folded(&source)
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{fmt::Write, time::Instant};
+
+use miette::Result;
+
+use crate::error::{ParseError, ReplError};
+
+use super::session::Session;
+
+// (name, usage, description)
+pub static COMMANDS: &[(&str, &str, &str)] = &[
+    (
+        ":stack",
+        ":stack",
+        "print every value on the stack, bottom first",
+    ),
+    (":clear", ":clear", "remove every value from the stack"),
+    (":boxes", ":boxes", "print every box and what it holds"),
+    (":macros", ":macros", "print every macro and its body"),
+    (
+        ":load",
+        ":load <file.roth>",
+        "evaluate a file in this session",
+    ),
+    (":reset", ":reset", "forget the stack, boxes and macros"),
+    (
+        ":type",
+        ":type <expr>",
+        "print the type of what <expr> leaves on top of the stack, without keeping it",
+    ),
+    (
+        ":time",
+        ":time <expr>",
+        "evaluate <expr> and print how long it took",
+    ),
+    (":help", ":help", "print this message"),
+    ("exit", "exit", "leave the repl"),
+];
+
+pub fn is_command(line: &str) -> bool {
+    line.trim_start().starts_with(':')
+}
+
+// Run a command, returning what to show
+pub fn run_command(session: &mut Session, line: &str) -> Result<String> {
+    let line = line.trim();
+    let mut output = String::new();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (&name[1..], arg.trim()),
+        None => (&line[1..], ""),
+    };
+
+    match name {
+        "stack" => print_stack(session, &mut output),
+        "clear" => session.runtime_mut().clear_stack(),
+        "boxes" => {
+            for (name, val_type, val) in session.runtime().boxes() {
+                let _ = writeln!(output, "{} ({}) = {}", name, val_type, val);
+            }
+        }
+        "macros" => {
+            for m in session.macros() {
+                let _ = writeln!(output, "{} => {}", m.name, m.body.trim());
+            }
+        }
+        "load" => {
+            let file_name = required(name, arg)?;
+            match std::fs::read_to_string(file_name) {
+                Ok(contents) => session.eval(contents.as_str())?,
+                Err(_) => return Err(ParseError::CannotReadFile(file_name.to_string()).into()),
+            }
+        }
+        "reset" => session.reset(),
+        "type" => {
+            let expr = required(name, arg)?;
+
+            // evaluate on a copy so `:type` never changes the session
+            let mut scratch = session.clone();
+            scratch.eval(expr)?;
+            match scratch.runtime().stack().top() {
+                Some(val) => {
+                    let _ = writeln!(output, "{}", val.clone().get_type(val.span()));
+                }
+                None => output.push_str("the stack is empty\n"),
+            }
+        }
+        "time" => {
+            let expr = required(name, arg)?;
+
+            let start = Instant::now();
+            session.eval(expr)?;
+            let _ = writeln!(output, "took {:?}", start.elapsed());
+        }
+        "help" => {
+            for (_, usage, description) in COMMANDS {
+                let _ = writeln!(output, "{:<20} {}", usage, description);
+            }
+        }
+        _ => return Err(ReplError::UnknownCommand(name.to_string()).into()),
+    }
+
+    Ok(output)
+}
+
+fn required<'a>(name: &str, arg: &'a str) -> Result<&'a str, ReplError> {
+    if arg.is_empty() {
+        let usage = COMMANDS
+            .iter()
+            .find(|(n, _, _)| n[1..] == *name)
+            .map(|(_, usage, _)| *usage)
+            .unwrap_or_default();
+        Err(ReplError::MissingArgument(name.to_string(), usage))
+    } else {
+        Ok(arg)
+    }
+}
+
+fn print_stack(session: &Session, output: &mut String) {
+    let stack = session.runtime().stack();
+    if stack.is_empty() {
+        output.push_str("<empty>\n");
+    }
+
+    for (depth, val) in stack.iter().enumerate() {
+        let _ = writeln!(
+            output,
+            "{}: {} ({})",
+            depth,
+            val,
+            val.clone().get_type(val.span())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    use crate::repl::{
+        commands::{is_command, run_command},
+        session::Session,
+    };
+    use expect_test::expect;
+
+    #[test]
+    fn test_commands() {
+        let file = std::env::temp_dir().join(format!("roth-load-{}.roth", std::process::id()));
+        fs::write(&file, "macro inc 1 + end\n4 inc").unwrap();
+
+        let mut session = Session::new(Arc::new(AtomicBool::new(false)));
+        let mut actual = String::new();
+        for line in [
+            "1 2",
+            ":type 3 \"a\"",
+            ":stack",
+            ":type",
+            ":clear",
+            ":stack",
+            "macro sq dup * end",
+            "box type::int x 3 sq x pack",
+            &format!(":load {}", file.display()),
+            ":stack",
+            ":boxes",
+            ":macros",
+            ":reset",
+            ":stack",
+            ":boxes",
+            ":macros",
+            "2 sq",
+            ":nope",
+        ] {
+            actual.push_str(&format!("> {}\n", line));
+            let result = match is_command(line) {
+                true => run_command(&mut session, line),
+                false => session.eval(line).map(|_| String::new()),
+            };
+            match result {
+                Ok(output) => actual.push_str(&output),
+                Err(e) => actual.push_str(&format!("error: {}\n", e)),
+            }
+        }
+        let _ = fs::remove_file(&file);
+
+        let expected = expect![[r#"
+            > 1 2
+            > :type 3 "a"
+            type::str
+            > :stack
+            0: 1 (type::int)
+            1: 2 (type::int)
+            > :type
+            error: `:type` needs an argument
+            > :clear
+            > :stack
+            <empty>
+            > macro sq dup * end
+            > box type::int x 3 sq x pack
+            > :load <file>
+            > :stack
+            0: 5 (type::int)
+            > :boxes
+            x (type::int) = 9
+            > :macros
+            sq => dup *
+            inc => 1 +
+            > :reset
+            > :stack
+            <empty>
+            > :boxes
+            > :macros
+            > 2 sq
+            error: Unknown box
+            > :nope
+            error: Unknown command `:nope`
+        "#]];
+        expected.assert_eq(&actual.replace(&file.display().to_string(), "<file>"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{cell::RefCell, rc::Rc};
+
+use nu_ansi_term::{Color, Style};
+use reedline::{Completer, Highlighter, Span, StyledText, ValidationResult, Validator};
+
+use crate::{
+    error::ParseError,
+    lexer::{Lexer, KEYWORDS},
+    token::TokenKind,
+};
+
+use super::commands::COMMANDS;
+
+// Names of the boxes and macros defined so far in the session. The repl
+// refreshes this after every line, and the editor helpers only read it.
+pub type SessionNames = Rc<RefCell<Vec<String>>>;
+
+pub struct RothHighlighter {
+    names: SessionNames,
+}
+
+impl RothHighlighter {
+    pub fn new(names: SessionNames) -> Self {
+        Self { names }
+    }
+
+    fn style_for(&self, kind: TokenKind, inner: &str) -> Style {
+        match kind {
+            TokenKind::Number | TokenKind::Boolean => Style::new().fg(Color::Purple),
+            TokenKind::String => Style::new().fg(Color::Green),
+            TokenKind::If
+            | TokenKind::End
+            | TokenKind::Macro
+            | TokenKind::Test
+            | TokenKind::Module
+            | TokenKind::Export
+            | TokenKind::Import
+            | TokenKind::Until
+            | TokenKind::Box
+            | TokenKind::Assert
+            | TokenKind::AssertEq
+            | TokenKind::AssertNe
+            | TokenKind::AssertType
+            | TokenKind::AssertStackDepth
+            | TokenKind::Breakpoint => Style::new().bold().fg(Color::Yellow),
+            TokenKind::Type
+            | TokenKind::TypeInt
+            | TokenKind::TypeStr
+            | TokenKind::TypeBool
+            | TokenKind::TypeBoxedInt
+            | TokenKind::TypeBoxedStr
+            | TokenKind::TypeBoxedBool => Style::new().fg(Color::Cyan),
+            TokenKind::Ident => {
+                if self.names.borrow().iter().any(|name| name == inner) {
+                    Style::new().fg(Color::Blue)
+                } else {
+                    Style::new()
+                }
+            }
+            _ => Style::new().fg(Color::LightBlue),
+        }
+    }
+}
+
+impl Highlighter for RothHighlighter {
+    fn highlight(&self, line: &str) -> StyledText {
+        let mut styled_text = StyledText::new();
+
+        if line.trim_start().starts_with(':') || line == "exit" {
+            styled_text.push((Style::new().fg(Color::Cyan), line.to_string()));
+            return styled_text;
+        }
+
+        // spans from the lexer count chars, not bytes
+        let chars: Vec<char> = line.chars().collect();
+        let slice = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+
+        let mut cursor = 0;
+        if let Ok(tokens) = Lexer::new(line).lex() {
+            for token in tokens {
+                let start = token.span.offset();
+                let end = start + token.span.len();
+
+                push_gap(&mut styled_text, slice(cursor, start));
+                styled_text.push((self.style_for(token.kind, &token.inner), slice(start, end)));
+                cursor = end;
+            }
+        }
+
+        // anything we couldn't lex (i.e. a string that isn't closed yet) is left as is
+        push_gap(&mut styled_text, slice(cursor, chars.len()));
+
+        styled_text
+    }
+}
+
+// Whitespace and comments between tokens
+fn push_gap(styled_text: &mut StyledText, gap: String) {
+    if gap.is_empty() {
+        return;
+    }
+
+    match gap.find("//") {
+        Some(idx) => {
+            styled_text.push((Style::new(), gap[..idx].to_string()));
+            styled_text.push((Style::new().dimmed(), gap[idx..].to_string()));
+        }
+        None => styled_text.push((Style::new(), gap)),
+    }
+}
+
+pub struct RothCompleter {
+    names: SessionNames,
+}
+
+impl RothCompleter {
+    pub fn new(names: SessionNames) -> Self {
+        Self { names }
+    }
+}
+
+impl Completer for RothCompleter {
+    fn complete(&self, line: &str, pos: usize) -> Vec<(Span, String)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |idx| idx + 1);
+        let prefix = &line[start..pos];
+
+        let mut candidates: Vec<String> = if start == 0 && prefix.starts_with(':') {
+            COMMANDS
+                .iter()
+                .map(|(name, _, _)| name.to_string())
+                .collect()
+        } else {
+            KEYWORDS
+                .iter()
+                .map(|(keyword, _)| keyword.to_string())
+                .chain(self.names.borrow().iter().cloned())
+                .collect()
+        };
+
+        candidates.retain(|candidate| candidate.starts_with(prefix) && candidate != prefix);
+        candidates.sort();
+        candidates.dedup();
+
+        candidates
+            .into_iter()
+            .map(|candidate| (Span::new(start, pos), candidate))
+            .collect()
+    }
+}
+
+// Keeps reading lines while an `if`, `until` or `macro` block (or a string)
+// is still open, so they can be written over several lines
+pub struct RothValidator;
+
+impl Validator for RothValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        if line.trim_start().starts_with(':') {
+            return ValidationResult::Complete;
+        }
+
+        match Lexer::new(line).lex() {
+            Ok(tokens) => {
+                let mut depth: isize = 0;
+                for token in tokens {
+                    match token.kind {
+                        TokenKind::If | TokenKind::Until | TokenKind::Macro | TokenKind::Test => {
+                            depth += 1
+                        }
+                        TokenKind::End => depth -= 1,
+                        _ => {}
+                    }
+                }
+
+                if depth > 0 {
+                    ValidationResult::Incomplete
+                } else {
+                    ValidationResult::Complete
+                }
+            }
+            Err(e) => match e.downcast_ref::<ParseError>() {
+                Some(ParseError::UnterminatedStringLiteral(..)) => ValidationResult::Incomplete,
+                _ => ValidationResult::Complete,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::repl::editor::{RothCompleter, RothValidator};
+    use expect_test::expect;
+    use reedline::{Completer, ValidationResult, Validator};
+
+    #[test]
+    fn test_validator() {
+        let actual: Vec<String> = [
+            "1 2 +",
+            "1 if",
+            "1 if 2 end",
+            "macro sq\n  dup *",
+            "macro sq\n  dup *\nend",
+            "true until false end",
+            "test \"t\" 1",
+            "\"not closed",
+            ":load",
+            "end",
+        ]
+        .iter()
+        .map(|line| {
+            let complete = match RothValidator.validate(line) {
+                ValidationResult::Complete => "complete",
+                ValidationResult::Incomplete => "incomplete",
+            };
+            format!("{:?}: {}", line, complete)
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "\"1 2 +\": complete",
+                "\"1 if\": incomplete",
+                "\"1 if 2 end\": complete",
+                "\"macro sq\\n  dup *\": incomplete",
+                "\"macro sq\\n  dup *\\nend\": complete",
+                "\"true until false end\": complete",
+                "\"test \\\"t\\\" 1\": incomplete",
+                "\"\\\"not closed\": incomplete",
+                "\":load\": complete",
+                "\"end\": complete",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_completer() {
+        let names = Rc::new(RefCell::new(vec![
+            String::from("count"),
+            String::from("square"),
+        ]));
+        let completer = RothCompleter::new(names);
+
+        let actual: Vec<String> = [
+            ("co", 2),
+            ("1 sq", 4),
+            ("dup sq 2", 2),
+            (":l", 2),
+            ("1 :l", 4),
+            ("count", 5),
+            ("1 type::b", 9),
+        ]
+        .iter()
+        .map(|(line, pos)| {
+            let candidates: Vec<String> = completer
+                .complete(line, *pos)
+                .into_iter()
+                .map(|(span, candidate)| format!("{}@{}..{}", candidate, span.start, span.end))
+                .collect();
+            format!("{:?} {}: {}", line, pos, candidates.join(" "))
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "\"co\" 2: count@0..2",
+                "\"1 sq\" 4: square@2..4",
+                "\"dup sq 2\" 2: dup@0..2",
+                "\":l\" 2: :load@0..2",
+                "\"1 :l\" 4: ",
+                "\"count\" 5: ",
+                "\"1 type::b\" 9: type::bool@2..9 type::box<bool>@2..9 type::box<int>@2..9 type::box<str>@2..9",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -0,0 +1,113 @@
+mod commands;
+mod editor;
+mod session;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use miette::Result;
+use reedline::{
+    default_emacs_keybindings, DefaultCompletionActionHandler, DefaultPrompt, Emacs,
+    FileBackedHistory, Reedline, ReedlineEvent, Signal, HISTORY_SIZE,
+};
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::cli::Options;
+use commands::{is_command, run_command};
+use editor::{RothCompleter, RothHighlighter, RothValidator, SessionNames};
+use session::Session;
+
+// i.e. ~/.local/share/roth/history on linux
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("roth").join("history"))
+}
+
+pub fn repl(options: &Options) -> Result<()> {
+    // While we're sitting at the prompt reedline sees ctrl-c as a key press, so this
+    // handler only fires while a line is being evaluated, and stops the runtime instead
+    // of killing the whole repl
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let handler_interrupt = interrupt.clone();
+    ctrlc::set_handler(move || handler_interrupt.store(true, Ordering::SeqCst))
+        .expect("failed to install the ctrl-c handler");
+
+    // the default bindings turn ctrl-d into "delete char", but we want it to quit
+    // on an empty line. ctrl-c already clears the line.
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+        KeyModifiers::CONTROL,
+        KeyCode::Char('d'),
+        ReedlineEvent::CtrlD,
+    );
+
+    let names: SessionNames = Rc::new(RefCell::new(Vec::new()));
+    let mut line_editor = Reedline::create()
+        .unwrap()
+        .with_edit_mode(Box::new(Emacs::new(keybindings)));
+    if let Some(history) =
+        history_path().and_then(|path| FileBackedHistory::with_file(HISTORY_SIZE, path).ok())
+    {
+        line_editor = line_editor.with_history(Box::new(history)).unwrap();
+    }
+
+    let mut line_editor = line_editor
+        .with_highlighter(Box::new(RothHighlighter::new(names.clone())))
+        .with_completion_action_handler(Box::new(
+            DefaultCompletionActionHandler::default()
+                .with_completer(Box::new(RothCompleter::new(names.clone()))),
+        ))
+        .with_validator(Box::new(RothValidator));
+    let prompt = DefaultPrompt::default();
+    let mut session = Session::new(interrupt.clone())
+        .with_capabilities(options.capabilities.clone())
+        .with_include_dirs(options.include_dirs.clone());
+
+    loop {
+        let sig = line_editor.read_line(&prompt).unwrap();
+        match sig {
+            Signal::Success(buffer) => {
+                if buffer == "exit" {
+                    break;
+                }
+
+                if is_command(&buffer) {
+                    match run_command(&mut session, &buffer) {
+                        Ok(output) => print!("{}", output),
+                        Err(e) => println!("{:?}", e),
+                    }
+                } else {
+                    match session.eval(&buffer) {
+                        Ok(()) => {
+                            if let Some(v) = session.runtime().stack().top() {
+                                println!("{}", v);
+                            }
+                        }
+                        Err(e) => println!("{:?}", e),
+                    }
+                }
+
+                *names.borrow_mut() = session.names();
+            }
+            // throw away whatever was typed and start a fresh line
+            Signal::CtrlC => {
+                line_editor.print_crlf().unwrap();
+            }
+            Signal::CtrlD => {
+                line_editor.print_crlf().unwrap();
+                break;
+            }
+            Signal::CtrlL => {
+                line_editor.clear_screen().unwrap();
+            }
+        }
+    }
+
+    // history is written to disk when `line_editor` is dropped
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use miette::Result;
+
+use crate::{
+    capabilities::Capabilities,
+    lexer::Lexer,
+    parser::Parser,
+    preprocessor::{Macro, PreProcessor},
+    runtime::Runtime,
+};
+
+// Everything the repl remembers between lines. Each line is appended to
+// `source` so spans of values pushed by earlier lines stay valid.
+#[derive(Clone)]
+pub struct Session {
+    source: String,
+    macros: Vec<Macro>,
+    runtime: Runtime,
+    interrupt: Arc<AtomicBool>,
+    // what `roth` was started with, for includes and words that need permission
+    capabilities: Capabilities,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Session {
+    pub fn new(interrupt: Arc<AtomicBool>) -> Self {
+        Self {
+            source: String::new(),
+            macros: Vec::new(),
+            runtime: Runtime::new(String::new(), VecDeque::new()).with_interrupt(interrupt.clone()),
+            interrupt,
+            capabilities: Capabilities::default(),
+            include_dirs: Vec::new(),
+        }
+    }
+
+    // Only include files and run words `capabilities` allow, on every line
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.runtime = self.runtime.with_capabilities(capabilities.clone());
+        self.capabilities = capabilities;
+        self
+    }
+
+    // Also look for includes in `dirs`, like `-I` does
+    pub fn with_include_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.include_dirs = dirs;
+        self
+    }
+
+    pub fn reset(&mut self) {
+        *self = Session::new(self.interrupt.clone())
+            .with_capabilities(self.capabilities.clone())
+            .with_include_dirs(self.include_dirs.clone());
+    }
+
+    pub fn eval(&mut self, input: &str) -> Result<()> {
+        // a ctrl-c that came in after the last line stopped running is for nothing
+        self.interrupt.store(false, Ordering::SeqCst);
+
+        let offset = self.source.len();
+        let mut preprocessor = PreProcessor::new(input)
+            .with_macros(self.macros.clone())
+            .with_offset(offset)
+            .with_include_dirs(self.include_dirs.clone())
+            .with_capabilities(self.capabilities.clone());
+        let expanded = preprocessor.expand()?;
+        self.macros = preprocessor.macros().to_vec();
+
+        self.source.push_str(&expanded);
+        self.source.push('\n');
+
+        let tokens = Lexer::new(expanded.as_str()).with_offset(offset).lex()?;
+        let ops = Parser::new(tokens, self.source.clone())
+            .with_inclusions(preprocessor.inclusions().to_vec())
+            .with_expansions(preprocessor.expansions().to_vec())
+            .parse()?;
+
+        self.runtime.load(self.source.clone(), ops);
+        self.runtime
+            .set_macro_names(self.macros.iter().map(|m| m.name.clone()).collect());
+        // a line that fails or gets interrupted leaves the stack as it found it,
+        // instead of however far it got
+        let stack = self.runtime.stack().clone();
+        if let Err(e) = self.runtime.run() {
+            self.runtime.restore_stack(stack);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    pub fn runtime_mut(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    pub fn macros(&self) -> &[Macro] {
+        &self.macros
+    }
+
+    // every box and macro name that's been defined so far
+    pub fn names(&self) -> Vec<String> {
+        self.runtime
+            .boxes()
+            .into_iter()
+            .map(|(name, _, _)| name.to_string())
+            .chain(self.macros.iter().map(|m| m.name.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{capabilities::Capabilities, repl::session::Session};
+    use expect_test::expect;
+
+    fn stack(session: &Session) -> String {
+        let vals: Vec<String> = session
+            .runtime()
+            .stack()
+            .iter()
+            .map(|v| v.literal())
+            .collect();
+        format!("[{}]", vals.join(", "))
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut session = Session::new(interrupt.clone());
+        session.eval("1").unwrap();
+
+        // what the ctrl-c handler does while a line runs forever
+        let handler_interrupt = interrupt.clone();
+        let handler = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handler_interrupt.store(true, Ordering::SeqCst);
+        });
+        let interrupted = session.eval("false until false end").unwrap_err();
+        handler.join().unwrap();
+
+        // a ctrl-c between lines doesn't stop the next one
+        interrupt.store(true, Ordering::SeqCst);
+        let after_interrupt = stack(&session);
+        session.eval("2 3 +").unwrap();
+
+        let actual = format!(
+            "{}\n{}\n{}",
+            interrupted.code().unwrap(),
+            after_interrupt,
+            stack(&session)
+        );
+        let expected = expect![[r#"
+            roth::interrupted
+            [1]
+            [1, 5]"#]];
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_failed_line() {
+        let mut session = Session::new(Arc::new(AtomicBool::new(false)));
+        session.eval("1 2").unwrap();
+
+        let failed = session.eval("3 4 + + + +").unwrap_err();
+
+        let actual = format!("{}\n{}", failed.code().unwrap(), stack(&session));
+        let expected = expect![[r#"
+            roth::empty_stack
+            [1, 2]"#]];
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let examples = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let include = format!("include(\"{}\")", examples.join("macro.roth").display());
+        let mut session = Session::new(Arc::new(AtomicBool::new(false)))
+            .with_capabilities(Capabilities::default().sandboxed());
+
+        let mut actual = Vec::new();
+        for line in [include.as_str(), "\"hi\" print", ":reset", include.as_str()] {
+            if line == ":reset" {
+                session.reset();
+                continue;
+            }
+            let outcome = match session.eval(line) {
+                Ok(_) => String::from("ok"),
+                Err(e) => e.code().unwrap().to_string(),
+            };
+            actual.push(outcome);
+        }
+
+        let expected = expect![[r#"
+            [
+                "roth::include_not_allowed",
+                "roth::word_not_allowed",
+                "roth::include_not_allowed",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -1,20 +1,38 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use miette::{Result, SourceSpan};
+use miette::{Report, Result, SourceSpan};
 
 use crate::{
-    error::RuntimeError,
+    capabilities::Capabilities,
+    coverage::Coverage,
+    error::{RuntimeError, TraceFrame, Traced},
+    lexer::KEYWORDS,
+    limits::{Budget, Limits},
+    modules::Scopes,
     op::{Op, OpKind},
+    preprocessor::{Expansion, Inclusion, StackEffect},
+    profile::Profiler,
     stack::Stack,
+    suggest::unknown_box_help,
+    trace::Tracer,
     val::{Val, ValKind, ValType},
 };
 
+#[derive(Clone)]
 enum EvalMode {
     Normal,
     If { last_span: SourceSpan },
     Until { body: Vec<Op> },
 }
 
+#[derive(Clone)]
 pub struct Runtime {
     source: String,
     ops: VecDeque<Op>,
@@ -22,6 +40,34 @@ pub struct Runtime {
     mode: EvalMode,
     box_ids: HashMap<String, (ValType, usize)>,
     boxes: Vec<Val>,
+    // bytes taken up by the strings in `boxes`, for `--max-memory`
+    box_bytes: usize,
+    interrupt: Option<Arc<AtomicBool>>,
+    // where macros were expanded and files were included, for backtraces
+    expansions: Vec<Expansion>,
+    inclusions: Vec<Inclusion>,
+    current_span: Option<SourceSpan>,
+    // only set in debug mode, when we check macros keep their stack effects
+    check_effects: bool,
+    frames: Vec<Frame>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    budget: Budget,
+    capabilities: Capabilities,
+    // only used to suggest names for unknown boxes
+    macro_names: Vec<String>,
+    scopes: Scopes,
+    // `print` takes its value without showing it
+    quiet: bool,
+}
+
+// A macro expansion we're currently running the body of
+#[derive(Clone)]
+struct Frame {
+    expansion: usize,
+    // stack depth when we entered it
+    depth: usize,
 }
 
 impl Runtime {
@@ -33,11 +79,190 @@ impl Runtime {
             stack: Stack::new(),
             box_ids: HashMap::new(),
             boxes: Vec::new(),
+            box_bytes: 0,
+            interrupt: None,
+            expansions: Vec::new(),
+            inclusions: Vec::new(),
+            current_span: None,
+            check_effects: false,
+            frames: Vec::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            budget: Budget::default(),
+            capabilities: Capabilities::default(),
+            macro_names: Vec::new(),
+            scopes: Scopes::default(),
+            quiet: false,
         }
     }
 
+    // Macros are expanded before we get here, so we have to be told about them
+    // to suggest them for a typo
+    pub fn with_macro_names(mut self, macro_names: Vec<String>) -> Self {
+        self.macro_names = macro_names;
+        self
+    }
+
+    pub fn set_macro_names(&mut self, macro_names: Vec<String>) {
+        self.macro_names = macro_names;
+    }
+
+    // Imports and exports, to point someone who left off a module's prefix at it
+    pub fn with_scopes(mut self, scopes: Scopes) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    // Errors inside of a macro or an included file get a backtrace through them
+    // i.e. while running the code before a test, which has its own output
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn with_expansions(mut self, expansions: Vec<Expansion>) -> Self {
+        self.expansions = expansions;
+        self
+    }
+
+    pub fn with_inclusions(mut self, inclusions: Vec<Inclusion>) -> Self {
+        self.inclusions = inclusions;
+        self
+    }
+
+    // Check every macro with a stack effect against it as it runs
+    pub fn with_effect_checks(mut self) -> Self {
+        self.check_effects = true;
+        self
+    }
+
+    // Print every op as it runs (see `Tracer`)
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn with_profiler(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    // What the profiler found, once the program has run
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn with_coverage(mut self, coverage: Coverage) -> Self {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    // Which ops ran, once the program has
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Stop with an error once the program goes over any of `limits`
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.budget = Budget::new(limits);
+        self
+    }
+
+    // Stop with an error before running a word `capabilities` don't allow
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    // Stop running (with `RuntimeError::Interrupted`) as soon as this flag gets set
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    // Swap in a new program while keeping the stack and boxes around, so
+    // the repl can keep evaluating lines against the same state
+    pub fn load(&mut self, source: String, ops: VecDeque<Op>) {
+        self.source = source;
+        self.ops = ops;
+        self.mode = EvalMode::Normal;
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn clear_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    // Put back a stack saved with `stack().clone()`
+    pub fn restore_stack(&mut self, stack: Stack) {
+        self.stack = stack;
+    }
+
+    // (name, type, current value) for every box, in creation order
+    pub fn boxes(&self) -> Vec<(&str, &ValType, &Val)> {
+        let mut boxes: Vec<_> = self
+            .box_ids
+            .iter()
+            .map(|(name, (val_type, box_id))| (name.as_str(), val_type, *box_id))
+            .collect();
+        boxes.sort_by_key(|(_, _, box_id)| *box_id);
+
+        boxes
+            .into_iter()
+            .map(|(name, val_type, box_id)| (name, val_type, &self.boxes[box_id]))
+            .collect()
+    }
+
     pub fn run(&mut self) -> Result<Stack> {
-        while let Some(op) = self.ops.pop_front() {
+        while self.step()? {}
+        self.finish()
+    }
+
+    // The op that will run next
+    pub fn next_op(&self) -> Option<&Op> {
+        self.ops.front()
+    }
+
+    // Run the next op, returning whether there was one. Once there aren't any
+    // left, `finish` makes sure the program ended properly.
+    pub fn step(&mut self) -> Result<bool> {
+        self.step_op().map_err(|error| self.traced(error))
+    }
+
+    pub fn finish(&mut self) -> Result<Stack> {
+        self.finish_ops().map_err(|error| self.traced(error))
+    }
+
+    fn step_op(&mut self) -> Result<bool> {
+        if let Some(op) = self.ops.pop_front() {
+            self.current_span = Some(op.span.clone());
+            let traced = self
+                .tracer
+                .as_ref()
+                .map(|_| (op.clone(), self.stack.clone()));
+            let started = self
+                .profiler
+                .as_ref()
+                .map(|_| (op.span.clone(), Instant::now()));
+            if let Some(coverage) = &mut self.coverage {
+                coverage.hit(&op.span);
+            }
+
+            if let Some(interrupt) = &self.interrupt {
+                if interrupt.swap(false, Ordering::SeqCst) {
+                    return Err(RuntimeError::Interrupted(self.source.clone(), op.span).into());
+                }
+            }
+            self.budget.step(&self.source, &op.span)?;
+            self.capabilities.check(&self.source, &op)?;
+
+            if self.check_effects {
+                self.check_expansions(&op.span)?;
+            }
+
             // We need to copy the body of loops
             if let EvalMode::Until { ref mut body } = self.mode {
                 body.push(op.clone());
@@ -53,7 +278,6 @@ impl Runtime {
                     }
                     EvalMode::If { .. } => self.mode = EvalMode::Normal,
                     EvalMode::Until { body } => {
-
                         //print!("adding {:#?}", body);
 
                         // add the loop body to ops
@@ -68,14 +292,31 @@ impl Runtime {
                     }
                 },
                 OpKind::CreateBox { .. } => self.eval_create_box(op)?,
-                OpKind::Pack => self.eval_pack_box()?,
-                OpKind::Unpack => self.eval_unpack_box()?,
+                OpKind::Pack => self.eval_pack_box(op)?,
+                OpKind::Unpack => self.eval_unpack_box(op)?,
                 OpKind::PushBox { .. } => self.eval_push_box(op)?,
                 OpKind::Until => self.eval_until(op)?,
                 _ => self.eval_simple(op)?,
             }
+
+            if let Some(span) = &self.current_span {
+                self.budget
+                    .check(&self.source, span, &self.stack, self.box_bytes)?;
+            }
+            if let (Some(tracer), Some((op, before))) = (&self.tracer, traced) {
+                eprintln!("{}", tracer.line(&self.source, &op, &before, &self.stack));
+            }
+            if let (Some(profiler), Some((span, started))) = (&mut self.profiler, started) {
+                profiler.record(&span, started.elapsed());
+            }
+
+            return Ok(true);
         }
 
+        Ok(false)
+    }
+
+    fn finish_ops(&mut self) -> Result<Stack> {
         // If we are still in a non normal eval mode here, then the program probably has structural errors
         // and it wasn't supposed to make it this far
         match &self.mode {
@@ -84,17 +325,204 @@ impl Runtime {
                 last_span.clone(),
             )
             .into()),
-            _ => Ok(self.stack.clone()),
+            _ => {
+                while let Some(frame) = self.frames.pop() {
+                    self.check_outputs(frame)?;
+                }
+                Ok(self.stack.clone())
+            }
+        }
+    }
+
+    // (x y) for binary ops, where y was on top
+    fn pop_pair(&mut self, op: &Op) -> Result<(Val, Val)> {
+        self.stack.require(2, &self.source, &op.span)?;
+        let y = self.stack.pop(&self.source, &op.span)?;
+        let x = self.stack.pop(&self.source, &op.span)?;
+
+        Ok((x, y))
+    }
+
+    // Attach the macros and includes the op that failed came from, innermost first
+    fn traced(&self, error: Report) -> Report {
+        let span = match &self.current_span {
+            Some(span) => span,
+            None => return error,
+        };
+        let contains = |outer: &SourceSpan| {
+            outer.offset() <= span.offset() && span.offset() < outer.offset() + outer.len()
+        };
+
+        let mut trace: Vec<TraceFrame> = self
+            .expansions
+            .iter()
+            .rev()
+            .filter(|expansion| contains(&expansion.span))
+            .map(|expansion| {
+                TraceFrame::Macro(
+                    self.source.clone(),
+                    expansion.name.clone(),
+                    expansion.span.clone(),
+                    expansion.definition.clone(),
+                )
+            })
+            .collect();
+        for inclusion in self.inclusions.iter().rev() {
+            if !contains(&inclusion.span) {
+                continue;
+            }
+            if let Some(line) = inclusion.line_of(span.offset()) {
+                trace.push(TraceFrame::Include(
+                    self.source.clone(),
+                    inclusion.file.clone(),
+                    line,
+                    span.clone(),
+                ));
+            }
+        }
+
+        if trace.is_empty() {
+            error
+        } else {
+            Traced { error, trace }.into()
         }
     }
 
+    // Leave every expansion that `span` is no longer inside of, then enter the
+    // ones it is, checking the stack effect at both ends
+    fn check_expansions(&mut self, span: &SourceSpan) -> Result<()> {
+        let contains = |expansion: &Expansion| {
+            let start = expansion.span.offset();
+            start <= span.offset() && span.offset() < start + expansion.span.len()
+        };
+
+        while let Some(frame) = self.frames.last() {
+            if contains(&self.expansions[frame.expansion]) {
+                break;
+            }
+            let frame = self.frames.pop().unwrap();
+            self.check_outputs(frame)?;
+        }
+
+        // expansions are sorted outer first, so frames nest the same way
+        for idx in 0..self.expansions.len() {
+            let expansion = &self.expansions[idx];
+            let effect = match &expansion.effect {
+                Some(effect) => effect,
+                None => continue,
+            };
+            if !contains(expansion) || self.frames.iter().any(|f| f.expansion == idx) {
+                continue;
+            }
+
+            let inputs = &effect.inputs;
+            let actual: Vec<Option<ValType>> = self
+                .stack
+                .iter()
+                .skip(self.stack.len().saturating_sub(inputs.len()))
+                .map(|val| val.val_type())
+                .collect();
+
+            if actual.len() < inputs.len() {
+                return Err(self.violated(
+                    idx,
+                    span,
+                    format!("only {} value(s) were on the stack", actual.len()),
+                ));
+            }
+            if let Some((got, expected)) = actual
+                .iter()
+                .zip(inputs)
+                .find(|(got, expected)| got.as_ref() != Some(expected))
+            {
+                return Err(self.violated(
+                    idx,
+                    span,
+                    format!(
+                        "it was given {} where it expects {}",
+                        describe(got),
+                        expected
+                    ),
+                ));
+            }
+
+            self.frames.push(Frame {
+                expansion: idx,
+                depth: self.stack.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn check_outputs(&self, frame: Frame) -> Result<()> {
+        let effect = self.effect_of(frame.expansion);
+        let span = &self.expansions[frame.expansion].span;
+
+        let expected_depth = frame.depth - effect.inputs.len() + effect.outputs.len();
+        if self.stack.len() != expected_depth {
+            return Err(self.violated(
+                frame.expansion,
+                span,
+                format!(
+                    "it left {} value(s) on the stack instead of {}",
+                    self.stack.len() as isize - (frame.depth - effect.inputs.len()) as isize,
+                    effect.outputs.len()
+                ),
+            ));
+        }
+
+        let actual: Vec<Option<ValType>> = self
+            .stack
+            .iter()
+            .skip(expected_depth - effect.outputs.len())
+            .map(|val| val.val_type())
+            .collect();
+        if let Some((got, expected)) = actual
+            .iter()
+            .zip(&effect.outputs)
+            .find(|(got, expected)| got.as_ref() != Some(expected))
+        {
+            return Err(self.violated(
+                frame.expansion,
+                span,
+                format!(
+                    "it returned {} where it should be {}",
+                    describe(got),
+                    expected
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn effect_of(&self, idx: usize) -> &StackEffect {
+        self.expansions[idx]
+            .effect
+            .as_ref()
+            .expect("ICE: only macros with a stack effect get frames")
+    }
+
+    fn violated(&self, idx: usize, span: &SourceSpan, what: String) -> Report {
+        let effect = self.effect_of(idx);
+        RuntimeError::StackEffectViolated(
+            self.source.clone(),
+            self.expansions[idx].name.clone(),
+            format!("{}, but {}", effect, what),
+            effect.span.clone(),
+            span.clone(),
+        )
+        .into()
+    }
+
     // if the top of the stack is NOT true
     //  1. continue evaluating until next corresponding end,
     //     storing the tokens we are popping off
     //  2. once we hit corresponding end, repeat, evaluating as normal
     fn eval_until(&mut self, until_op: Op) -> Result<()> {
         // TODO: nested untils, if's inside untils
-        let val = self.stack.pop()?;
+        let val = self.stack.pop(&self.source, &until_op.span)?;
 
         match val.kind() {
             ValKind::Bool { val } => {
@@ -102,11 +530,17 @@ impl Runtime {
                     self.skip_until_corresponding_end(until_op)?;
                     println!("loop eval'd true - skipping body");
                 } else {
-                    self.mode = EvalMode::Until { body: vec![until_op] }
+                    self.mode = EvalMode::Until {
+                        body: vec![until_op],
+                    }
                 }
             }
             _ => {
-                todo!("until's only evaluate bools")
+                return Err(RuntimeError::UntilsExpectBooleans(
+                    self.source.to_string(),
+                    until_op.span,
+                )
+                .into())
             }
         }
         Ok(())
@@ -117,10 +551,13 @@ impl Runtime {
         // if condition is false, skip all tokens until the next CORRESPONDING 'else' or 'end'
         //      we keep track of the corresponding 'end' via 'if_counter'
 
-        let val = self.stack.pop()?;
+        let val = self.stack.pop(&self.source, &op.span)?;
 
         match val.kind() {
             ValKind::Bool { val } => {
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.branch(&op.span, *val);
+                }
                 if *val {
                     // Keep evaluating as normal
                     self.mode = EvalMode::If { last_span: op.span };
@@ -198,9 +635,8 @@ impl Runtime {
         }
     }
 
-    fn eval_pack_box(&mut self) -> Result<()> {
-        let b = self.stack.pop()?;
-        let val = self.stack.pop()?;
+    fn eval_pack_box(&mut self, op: Op) -> Result<()> {
+        let (val, b) = self.pop_pair(&op)?;
 
         match b.kind() {
             ValKind::BoxedInt { box_id } => match val.kind() {
@@ -222,7 +658,8 @@ impl Runtime {
             },
             ValKind::BoxedStr { box_id } => match val.kind() {
                 ValKind::Str { .. } => {
-                    if self.boxes.get(*box_id).is_some() {
+                    if let Some(old) = self.boxes.get(*box_id) {
+                        self.box_bytes = self.box_bytes - old.bytes() + val.bytes();
                         self.boxes[*box_id] = val;
                     } else {
                         unreachable!("ICE: invalid id");
@@ -261,8 +698,8 @@ impl Runtime {
         Ok(())
     }
 
-    fn eval_unpack_box(&mut self) -> Result<()> {
-        let b = self.stack.pop()?;
+    fn eval_unpack_box(&mut self, op: Op) -> Result<()> {
+        let b = self.stack.pop(&self.source, &op.span)?;
 
         match b.kind() {
             ValKind::BoxedInt { box_id } => {
@@ -306,7 +743,19 @@ impl Runtime {
                 self.stack.push(val);
                 Ok(())
             } else {
-                Err(RuntimeError::UnknownBox(self.source.clone(), op.span).into())
+                let candidates: Vec<&str> = KEYWORDS
+                    .iter()
+                    .map(|(keyword, _)| *keyword)
+                    .chain(self.box_ids.keys().map(|name| name.as_str()))
+                    .chain(self.macro_names.iter().map(|name| name.as_str()))
+                    .collect();
+
+                Err(RuntimeError::UnknownBox(
+                    self.source.clone(),
+                    unknown_box_help(&name, &candidates, &self.scopes),
+                    op.span,
+                )
+                .into())
             }
         } else {
             unreachable!("eval_push_box called with non PushBox op")
@@ -352,103 +801,134 @@ impl Runtime {
                 },
             )),
             OpKind::Add => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.add(y, self.source.as_str(), op.span)?);
             }
             OpKind::Sub => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.sub(y, self.source.as_str(), op.span)?);
             }
             OpKind::Mul => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.mul(y, self.source.as_str(), op.span)?);
             }
             OpKind::Div => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.div(y, self.source.as_str(), op.span)?);
             }
             OpKind::Print => {
-                let x = self.stack.pop()?;
-                x.print();
+                let x = self.stack.pop(&self.source, &op.span)?;
+                if !self.quiet {
+                    x.print();
+                }
             }
             OpKind::Or => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.or(y, self.source.as_str(), op.span)?);
             }
             OpKind::And => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.and(y, self.source.as_str(), op.span)?);
             }
             OpKind::Not => {
-                let val = self.stack.pop()?;
+                let val = self.stack.pop(&self.source, &op.span)?;
 
                 self.stack.push(val.not(self.source.as_str(), op.span)?);
             }
             OpKind::Eq => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.eq(y, self.source.as_str(), op.span)?);
             }
             OpKind::LessThan => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.lt(y, self.source.as_str(), op.span)?);
             }
             OpKind::GreaterThan => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.gt(y, self.source.as_str(), op.span)?);
             }
             OpKind::LessThanEq => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.lte(y, self.source.as_str(), op.span)?);
             }
             OpKind::GreaterThanEq => {
-                let y = self.stack.pop()?;
-                let x = self.stack.pop()?;
+                let (x, y) = self.pop_pair(&op)?;
 
                 self.stack.push(x.gte(y, self.source.as_str(), op.span)?);
             }
             OpKind::Dup => {
-                self.stack.dup()?;
+                self.stack.dup(&self.source, &op.span)?;
             }
             OpKind::Drop => {
-                self.stack.pop()?;
+                self.stack.pop(&self.source, &op.span)?;
             }
             OpKind::Swap => {
-                self.stack.swap()?;
+                self.stack.swap(&self.source, &op.span)?;
             }
             OpKind::Over => {
-                self.stack.over()?;
+                self.stack.over(&self.source, &op.span)?;
             }
             OpKind::Rot => {
-                self.stack.rot()?;
+                self.stack.rot(&self.source, &op.span)?;
             }
             OpKind::GetType => {
-                let x = self.stack.pop()?;
+                let x = self.stack.pop(&self.source, &op.span)?;
                 self.stack.push(x.get_type(op.span))
             }
             OpKind::Assert => {
-                let x = self.stack.pop()?;
+                let x = self.stack.pop(&self.source, &op.span)?;
                 x.assert(self.source.as_str(), op.span)?
             }
+            OpKind::AssertEq => {
+                let (actual, expected) = self.pop_pair(&op)?;
+                actual.assert_eq(expected, self.source.as_str(), op.span)?
+            }
+            OpKind::AssertNe => {
+                let (actual, other) = self.pop_pair(&op)?;
+                actual.assert_ne(other, self.source.as_str(), op.span)?
+            }
+            OpKind::AssertType => {
+                let (val, expected) = self.pop_pair(&op)?;
+                val.assert_type(expected, self.source.as_str(), op.span)?
+            }
+            // only does something in `roth debug`
+            OpKind::Breakpoint => {}
+            OpKind::AssertStackDepth => {
+                let depth = self.stack.pop(&self.source, &op.span)?;
+                let expected = match depth.kind() {
+                    ValKind::Int { val } => *val,
+                    _ => {
+                        return Err(RuntimeError::InvalidAssertStackDepth(
+                            self.source.clone(),
+                            depth.span(),
+                            op.span,
+                        )
+                        .into())
+                    }
+                };
+                // the depth itself doesn't count
+                let actual = self.stack.len();
+                if expected != actual as i128 {
+                    return Err(RuntimeError::AssertStackDepthFailed(
+                        self.source.clone(),
+                        expected,
+                        actual,
+                        depth.span(),
+                        op.span,
+                    )
+                    .into());
+                }
+            }
 
             _ => unreachable!("non simple opkind should have already been processed"),
         }
@@ -456,3 +936,80 @@ impl Runtime {
         Ok(())
     }
 }
+
+fn describe(val_type: &Option<ValType>) -> String {
+    match val_type {
+        Some(val_type) => val_type.to_string(),
+        None => String::from("a type"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, parser::Parser, preprocessor::PreProcessor, runtime::Runtime};
+    use expect_test::expect;
+
+    #[test]
+    fn test_backtrace() {
+        let mut preprocessor = PreProcessor::new("macro sq dup * end\nmacro boom sq + end\n3 boom");
+        let source = preprocessor.expand().unwrap();
+        let tokens = Lexer::new(&source).lex().unwrap();
+        let ops = Parser::new(tokens, source.clone()).parse().unwrap();
+        let error = Runtime::new(source, ops)
+            .with_expansions(preprocessor.expansions().to_vec())
+            .run()
+            .unwrap_err();
+
+        let actual: Vec<String> = std::iter::once(error.to_string())
+            .chain(error.related().unwrap().map(|frame| frame.to_string()))
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "Empty stack",
+                "in macro `boom`",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_assertions() {
+        let actual: Vec<String> = [
+            "1 2 + 3 assert-eq",
+            "1 2 + \"3\" assert-eq",
+            "true false assert-ne",
+            "\"a\" type::str assert-type",
+            "1 type::bool assert-type",
+            "type::int type::bool assert-type",
+            "1 2 2 assert-stack-depth",
+            "1 2 assert-stack-depth",
+        ]
+        .iter()
+        .map(|source| {
+            let tokens = Lexer::new(source).lex().unwrap();
+            let ops = Parser::new(tokens, source.to_string()).parse().unwrap();
+            match Runtime::new(source.to_string(), ops).run() {
+                Ok(_) => String::from("ok"),
+                Err(e) => format!("{}: {}", e.code().unwrap(), e.help().unwrap()),
+            }
+        })
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                "ok",
+                "roth::assert_eq_failed: expected \"3\", but got 3",
+                "ok",
+                "ok",
+                "roth::assert_type_failed: expected a type::bool, but got a type::int",
+                "roth::assert_type_failed: expected a type::bool, but got a type",
+                "ok",
+                "roth::assert_stack_depth_failed: expected 2 value(s) on the stack, but there were 1",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -1,47 +1,100 @@
 use std::collections::VecDeque;
 
+use miette::SourceSpan;
+
 use crate::{error::RuntimeError, val::Val};
 
 #[derive(Debug, Clone)]
 pub struct Stack {
-    vals: VecDeque<Val>
+    vals: VecDeque<Val>,
+    // kept up to date as values come and go, so limits don't have to add it up
+    bytes: usize,
 }
 
 impl Stack {
     pub fn new() -> Self {
         Self {
             vals: VecDeque::new(),
+            bytes: 0,
         }
     }
 
     pub fn push(&mut self, val: Val) {
+        self.bytes += val.bytes();
         self.vals.push_back(val);
     }
 
-    pub fn pop(&mut self) -> Result<Val, RuntimeError> {
-        match self.vals.pop_back() {
-            Some(v) => Ok(v),
-            None => Err(RuntimeError::EmptyStackError),
+    // Make sure there are at least `needed` values for the op at `span`
+    pub fn require(
+        &self,
+        needed: usize,
+        source: &str,
+        span: &SourceSpan,
+    ) -> Result<(), RuntimeError> {
+        if self.vals.len() < needed {
+            return Err(RuntimeError::EmptyStackError(
+                source.to_string(),
+                needed,
+                self.vals.len(),
+                span.clone(),
+            ));
         }
+
+        Ok(())
     }
 
-    pub fn peek(&self) -> Result<&Val, RuntimeError> {
-        match self.vals.back() {
-            Some(v) => Ok(v),
-            None => Err(RuntimeError::EmptyStackError),
-        }
+    pub fn pop(&mut self, source: &str, span: &SourceSpan) -> Result<Val, RuntimeError> {
+        self.require(1, source, span)?;
+        let val = self.vals.pop_back().unwrap();
+        self.bytes -= val.bytes();
+        Ok(val)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vals.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vals.clear();
+        self.bytes = 0;
+    }
+
+    // bytes taken up by the strings on the stack
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // bottom of the stack first
+    pub fn iter(&self) -> impl Iterator<Item = &Val> {
+        self.vals.iter()
+    }
+
+    pub fn peek(&self, source: &str, span: &SourceSpan) -> Result<&Val, RuntimeError> {
+        self.require(1, source, span)?;
+        Ok(self.vals.back().unwrap())
+    }
+
+    // for when there's no op to blame, i.e. showing the result in the repl
+    pub fn top(&self) -> Option<&Val> {
+        self.vals.back()
     }
 
     // (a -- a a)
-    pub fn dup(&mut self) -> Result<(), RuntimeError> {
-        self.vals.push_back(self.peek()?.clone());
+    pub fn dup(&mut self, source: &str, span: &SourceSpan) -> Result<(), RuntimeError> {
+        let val = self.peek(source, span)?.clone();
+        self.push(val);
         Ok(())
     }
 
     // ( a b -- b a)
-    pub fn swap(&mut self) -> Result<(), RuntimeError> {
-        let b = self.pop()?;
-        let a = self.pop()?;
+    pub fn swap(&mut self, source: &str, span: &SourceSpan) -> Result<(), RuntimeError> {
+        self.require(2, source, span)?;
+        let b = self.pop(source, span)?;
+        let a = self.pop(source, span)?;
 
         self.push(b);
         self.push(a);
@@ -50,9 +103,10 @@ impl Stack {
     }
 
     // ( a b -- a b a)
-    pub fn over(&mut self) -> Result<(), RuntimeError> {
-        let b = self.pop()?;
-        let a = self.pop()?;
+    pub fn over(&mut self, source: &str, span: &SourceSpan) -> Result<(), RuntimeError> {
+        self.require(2, source, span)?;
+        let b = self.pop(source, span)?;
+        let a = self.pop(source, span)?;
 
         self.push(a.clone());
         self.push(b);
@@ -62,10 +116,11 @@ impl Stack {
     }
 
     // (a b c -- b c a)
-    pub fn rot(&mut self) -> Result<(), RuntimeError> {
-        let c = self.pop()?;
-        let b = self.pop()?;
-        let a = self.pop()?;
+    pub fn rot(&mut self, source: &str, span: &SourceSpan) -> Result<(), RuntimeError> {
+        self.require(3, source, span)?;
+        let c = self.pop(source, span)?;
+        let b = self.pop(source, span)?;
+        let a = self.pop(source, span)?;
 
         self.push(b);
         self.push(c);
@@ -0,0 +1,107 @@
+use crate::modules::Scopes;
+
+// The candidate closest to `word`, as long as it's close enough to be a typo
+pub fn did_you_mean<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    // about one mistake for every three characters, but some of the word has to
+    // be right, or `x` would suggest `+`
+    let length = word.chars().count();
+    let max_distance = (length / 3).max(1).min(length.saturating_sub(1));
+
+    candidates
+        .iter()
+        .filter(|candidate| **candidate != word)
+        .map(|candidate| (edit_distance(word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// Help for an identifier that isn't a box, which is what any unknown word ends up as
+pub fn unknown_box_help(name: &str, candidates: &[&str], scopes: &Scopes) -> String {
+    // i.e. `square` after `import("math.roth")`, which exports it as `math::square`
+    if let Some(qualified) = scopes.imported_name(name) {
+        return format!(
+            "did you mean `{}`? Names from imported modules need their prefix",
+            qualified
+        );
+    }
+
+    match did_you_mean(name, candidates) {
+        Some(suggestion) => format!("did you mean `{}`?", suggestion),
+        None => String::from("couldn't find any boxes with this name. Is it a typo?"),
+    }
+}
+
+// Levenshtein distance where swapping two neighbouring chars also counts as one edit,
+// since that's the most common typo (`pirnt`)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between a[..i] and b[..j]
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::suggest::did_you_mean;
+    use expect_test::expect;
+
+    #[test]
+    fn test_did_you_mean() {
+        let candidates = ["dup", "drop", "print", "swap", "counter", "+", "x1"];
+        let actual: Vec<_> = [
+            "dupp", "pirnt", "drp", "countr", "xyz", "dup", "x", "-", "x2",
+        ]
+        .iter()
+        .map(|word| did_you_mean(word, &candidates))
+        .collect();
+
+        let expected = expect![[r#"
+            [
+                Some(
+                    "dup",
+                ),
+                Some(
+                    "print",
+                ),
+                Some(
+                    "dup",
+                ),
+                Some(
+                    "counter",
+                ),
+                None,
+                None,
+                None,
+                None,
+                Some(
+                    "x1",
+                ),
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
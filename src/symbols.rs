@@ -0,0 +1,392 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    capabilities::Capabilities,
+    json,
+    lexer::Lexer,
+    modules::{imported_file, module_key, qualify, Resolved, Scopes},
+    preprocessor::{included_files, locate_include},
+    token::{Token, TokenKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Macro,
+    Box,
+}
+
+// Where a name shows up, 1-based like `LineMap::position_of`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    // the file as the program would include it, `None` for the program itself
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+impl Location {
+    // `file:line:column`, with includes next to `program` since that's where
+    // they're looked for
+    pub fn describe(&self, program: &str) -> String {
+        let file = match &self.file {
+            None => program.to_string(),
+            Some(file) => match Path::new(program).parent() {
+                Some(dir) => dir
+                    .join(file.trim_start_matches("./"))
+                    .display()
+                    .to_string(),
+                None => file.clone(),
+            },
+        };
+
+        format!("{}:{}:{}", file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub definition: Location,
+    pub uses: Vec<Location>,
+}
+
+// Every macro and box in a program and the files it includes, with where each
+// one is defined and used. Names from modules are qualified (`math::sq`) like
+// the parser does. Works on the source as written, so nothing needs to be
+// expanded (or even valid) for it.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    symbols: Vec<Symbol>,
+    // what the modules import and export
+    scopes: Scopes,
+}
+
+// A file in the program, and the module it's part of
+struct SourceFile {
+    file: Option<String>,
+    text: String,
+    tokens: Vec<Token>,
+    module: Option<String>,
+}
+
+impl SymbolIndex {
+    // The program is in `dir`, and includes are looked for from there and then
+    // in `search_path`, like the preprocessor does. Files `capabilities` don't
+    // let the program include aren't read.
+    pub fn new(
+        source: &str,
+        dir: &Path,
+        search_path: &[PathBuf],
+        capabilities: &Capabilities,
+    ) -> Self {
+        let files = std::iter::once((None, source.to_string(), None)).chain(
+            included_files(source, dir, search_path, capabilities)
+                .into_iter()
+                .map(|(file, contents, includer)| (Some(file), contents, includer)),
+        );
+
+        // files that start with `module <name>` are modules, and the rest are part
+        // of whatever included them. (file, name, key) of every module.
+        let mut modules: Vec<(String, String, String)> = Vec::new();
+        let mut sources: Vec<SourceFile> = Vec::new();
+        for (file, text, includer) in files {
+            let tokens = Lexer::new(&text).lex_recovering().0;
+            let declared = match (tokens.first(), tokens.get(1)) {
+                (Some(keyword), Some(name)) if keyword.kind == TokenKind::Module => {
+                    Some(name.inner.clone())
+                }
+                _ => None,
+            };
+            let module = match (declared, &file) {
+                (Some(name), Some(file)) => {
+                    let taken: Vec<String> =
+                        modules.iter().map(|(_, _, key)| key.clone()).collect();
+                    let key = module_key(&name, &taken);
+                    modules.push((file.clone(), name, key.clone()));
+                    Some(key)
+                }
+                _ => sources
+                    .iter()
+                    .find(|source| source.file.is_some() && source.file == includer)
+                    .and_then(|source| source.module.clone()),
+            };
+            sources.push(SourceFile {
+                file,
+                text,
+                tokens,
+                module,
+            });
+        }
+
+        let mut index = SymbolIndex::default();
+        for source in &sources {
+            for (idx, token) in source.tokens.iter().enumerate() {
+                let module = source.module.as_deref();
+                match token.kind {
+                    TokenKind::Import => {
+                        let written = imported_file(&token.inner);
+                        let found =
+                            locate_include(written, source.file.as_deref(), dir, search_path);
+                        let imported = found.and_then(|(name, _)| {
+                            modules.iter().find(|(file, _, _)| *file == name)
+                        });
+                        if let Some((_, name, key)) = imported {
+                            let alias =
+                                match (source.tokens.get(idx + 1), source.tokens.get(idx + 2)) {
+                                    (Some(keyword), Some(alias)) if keyword.inner == "as" => {
+                                        &alias.inner
+                                    }
+                                    _ => name,
+                                };
+                            index.scopes.import(module, alias, key);
+                        }
+                    }
+                    TokenKind::Export => {
+                        let name = match source.tokens.get(idx + 1).map(|t| t.kind) {
+                            Some(TokenKind::Macro) => source.tokens.get(idx + 2),
+                            Some(TokenKind::Box) => source.tokens.get(idx + 3),
+                            _ => None,
+                        };
+                        if let (Some(_), Some(name)) = (module, name) {
+                            index.scopes.export(qualify(module, &name.inner));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // everything is defined before uses are looked for, since a macro can
+        // use one from a file included after it
+        for source in &sources {
+            for (kind, token) in definitions_in(&source.tokens) {
+                let name = qualify(source.module.as_deref(), &token.inner);
+                if index.symbol(&name).is_none() {
+                    index.symbols.push(Symbol {
+                        name,
+                        kind,
+                        definition: location(&source.file, &source.text, token),
+                        uses: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        for source in &sources {
+            let module = source.module.as_deref();
+            let defined: Vec<usize> = definitions_in(&source.tokens)
+                .map(|(_, token)| token.span.offset())
+                .collect();
+            for (idx, token) in source.tokens.iter().enumerate() {
+                if token.kind != TokenKind::Ident
+                    || defined.contains(&token.span.offset())
+                    || names_module(&source.tokens, idx)
+                {
+                    continue;
+                }
+                // uses of names a module doesn't export still count
+                let name = match index.scopes.resolve(&token.inner, module) {
+                    Resolved::Name(name) | Resolved::NotExported(name) => name,
+                    Resolved::UnknownModule => continue,
+                };
+                if let Some(symbol) = index.symbols.iter_mut().find(|s| s.name == name) {
+                    symbol
+                        .uses
+                        .push(location(&source.file, &source.text, token));
+                }
+            }
+        }
+
+        index
+    }
+
+    // `name` as the program would write it, or what a module calls it
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        match self.scopes.resolve(name, None) {
+            Resolved::Name(qualified) | Resolved::NotExported(qualified) => {
+                self.symbol(&qualified).or_else(|| self.symbol(name))
+            }
+            Resolved::UnknownModule => self.symbol(name),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.symbols
+            .iter()
+            .map(|symbol| symbol.name.as_str())
+            .collect()
+    }
+
+    // The symbol defined or used at a 1-based line and column
+    pub fn at(&self, file: Option<&str>, line: usize, column: usize) -> Option<&Symbol> {
+        let covers = |at: &Location| {
+            at.file.as_deref() == file
+                && at.line == line
+                && at.column <= column
+                && column <= at.column + at.len
+        };
+
+        self.symbols
+            .iter()
+            .find(|symbol| covers(&symbol.definition) || symbol.uses.iter().any(covers))
+    }
+}
+
+// `macro <name>` and `box <type> <name>`
+fn definitions_in(tokens: &[Token]) -> impl Iterator<Item = (SymbolKind, &Token)> {
+    tokens.iter().enumerate().filter_map(|(idx, token)| {
+        let previous = |n: usize| idx.checked_sub(n).map(|idx| tokens[idx].kind);
+        match token.kind {
+            TokenKind::Ident if previous(1) == Some(TokenKind::Macro) => {
+                Some((SymbolKind::Macro, token))
+            }
+            TokenKind::Ident if previous(2) == Some(TokenKind::Box) => {
+                Some((SymbolKind::Box, token))
+            }
+            _ => None,
+        }
+    })
+}
+
+// `as` and the name in `import("file") as <name>`, and the name in
+// `module <name>`, which aren't macros or boxes
+fn names_module(tokens: &[Token], idx: usize) -> bool {
+    let previous = |n: usize| idx.checked_sub(n).map(|idx| &tokens[idx]);
+    match (previous(1), previous(2)) {
+        (Some(keyword), _) if keyword.kind == TokenKind::Module => true,
+        (Some(import), _) if import.kind == TokenKind::Import => tokens[idx].inner == "as",
+        (Some(keyword), Some(import)) => keyword.inner == "as" && import.kind == TokenKind::Import,
+        _ => false,
+    }
+}
+
+fn location(file: &Option<String>, text: &str, token: &Token) -> Location {
+    let (line, column) = json::position(&text, token.span.offset()).unwrap_or((1, 1));
+    Location {
+        file: file.clone(),
+        line,
+        column,
+        len: token.span.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{capabilities::Capabilities, symbols::SymbolIndex};
+    use expect_test::expect;
+
+    #[test]
+    fn test_symbol_index() {
+        // `examples/` has files to include
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let source = "include(\"./macro.roth\")
+box type::int x
+1 2 add x pack
+x unpack print";
+        let index = SymbolIndex::new(source, &dir, &[], &Capabilities::default());
+
+        let mut actual = Vec::new();
+        for name in ["add", "x"] {
+            let symbol = index.get(name).unwrap();
+            actual.push(format!(
+                "{} {:?} {}",
+                name,
+                symbol.kind,
+                symbol.definition.describe("examples/t.roth")
+            ));
+            for at in &symbol.uses {
+                actual.push(format!("  {}", at.describe("examples/t.roth")));
+            }
+        }
+        let at = index.at(None, 3, 6).map(|symbol| symbol.name.as_str());
+        actual.push(format!("at 3:6: {:?}", at));
+
+        let expected = expect![[r#"
+            [
+                "add Macro examples/macro.roth:5:7",
+                "  examples/t.roth:3:5",
+                "  examples/macro.roth:12:5",
+                "x Box examples/t.roth:2:15",
+                "  examples/t.roth:3:9",
+                "  examples/t.roth:4:1",
+                "at 3:6: Some(\"add\")",
+            ]
+        "#]];
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_symbol_index_modules() {
+        let dir = std::env::temp_dir().join(format!("roth-symbols-{}", std::process::id()));
+        let files = [
+            (
+                "lib/math.roth",
+                "module math\nbox type::int calls\nexport macro sq dup * calls unpack 1 + calls pack end\n",
+            ),
+            (
+                "lib/stats.roth",
+                "module stats\nimport(\"math.roth\") as m\nbox type::int calls\nexport macro sumsq m::sq swap m::sq + calls unpack drop end\n",
+            ),
+        ];
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        // every file has its own `calls`
+        let source = "import(\"lib/math.roth\")
+import(\"lib/stats.roth\") as st
+box type::int calls
+2 math::sq 1 2 st::sumsq calls pack";
+        let index = SymbolIndex::new(source, &dir, &[], &Capabilities::default());
+
+        let main = dir.join("main.roth").display().to_string();
+        let mut actual = Vec::new();
+        for name in [
+            "calls",
+            "math::calls",
+            "stats::calls",
+            "math::sq",
+            "st::sumsq",
+        ] {
+            let symbol = index.get(name).unwrap();
+            actual.push(format!(
+                "{} ({}) {}",
+                name,
+                symbol.name,
+                symbol.definition.describe(&main)
+            ));
+            for at in &symbol.uses {
+                actual.push(format!("  {}", at.describe(&main)));
+            }
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+        let actual = format!("{:#?}", actual).replace(&dir.display().to_string(), "<tmp>");
+        let expected = expect![[r#"
+            [
+                "calls (calls) <tmp>/main.roth:3:15",
+                "  <tmp>/main.roth:4:26",
+                "math::calls (math::calls) <tmp>/lib/math.roth:2:15",
+                "  <tmp>/lib/math.roth:3:23",
+                "  <tmp>/lib/math.roth:3:40",
+                "stats::calls (stats::calls) <tmp>/lib/stats.roth:3:15",
+                "  <tmp>/lib/stats.roth:4:39",
+                "math::sq (math::sq) <tmp>/lib/math.roth:3:14",
+                "  <tmp>/main.roth:4:3",
+                "  <tmp>/lib/stats.roth:4:20",
+                "  <tmp>/lib/stats.roth:4:31",
+                "st::sumsq (stats::sumsq) <tmp>/lib/stats.roth:4:14",
+                "  <tmp>/main.roth:4:16",
+            ]"#]];
+        expected.assert_eq(&actual);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{Report, Result};
+
+use crate::{
+    cli::{Input, Options},
+    coverage::{self, Coverage},
+    error::{CliError, Located},
+    lexer::Lexer,
+    modules::Scopes,
+    op::Op,
+    parser::{Parser, TestCase},
+    preprocessor::{Expansion, Inclusion, LineMap},
+    runtime::Runtime,
+};
+
+// `roth test`: run every `test` block in the program, or in every `.roth` file
+// under a directory (the current one if there's no input)
+pub fn run_tests(input: Option<&Input>, options: &Options) -> Result<()> {
+    let programs = match input {
+        None => discover(Path::new(".")),
+        Some(Input::File(path)) if Path::new(path).is_dir() => discover(Path::new(path)),
+        Some(input) => vec![input.clone()],
+    };
+
+    let mut passed = 0;
+    let mut failures: Vec<(String, String, Report)> = Vec::new();
+    let mut coverages = Vec::new();
+    for program in &programs {
+        let loaded = match load(program, options) {
+            Ok(loaded) => loaded,
+            Err(report) => {
+                println!("error in {}", program.name());
+                failures.push((program.name().to_string(), String::new(), report));
+                continue;
+            }
+        };
+
+        let tests: Vec<TestCase> = loaded
+            .tests
+            .into_iter()
+            .filter(|test| match &options.test_filter {
+                Some(filter) => test.name.contains(filter.as_str()),
+                None => true,
+            })
+            .collect();
+        if tests.is_empty() {
+            continue;
+        }
+
+        println!("\nrunning {} test(s) in {}", tests.len(), program.name());
+        // what ran in any of the tests
+        let new_coverage = |ops: &VecDeque<Op>| {
+            Coverage::new(
+                program.name(),
+                &loaded.source,
+                loaded.lines.clone(),
+                loaded.expansions.clone(),
+                ops,
+            )
+        };
+        let mut program_coverage = new_coverage(&VecDeque::new());
+        for test in tests {
+            let setup = loaded.ops.iter().take(test.setup).cloned().collect();
+            let mut runtime = Runtime::new(loaded.expanded_source.clone(), setup)
+                .with_expansions(loaded.expansions.clone())
+                .with_inclusions(loaded.inclusions.clone())
+                .with_macro_names(loaded.macro_names.clone())
+                .with_scopes(loaded.scopes.clone())
+                .with_limits(options.limits)
+                .with_capabilities(options.capabilities.clone());
+            if options.coverage.is_some() {
+                runtime = runtime.with_coverage(new_coverage(&test.ops));
+            }
+            let result = run_test(&mut runtime, &loaded.expanded_source, &test);
+            if let Some(coverage) = runtime.coverage() {
+                program_coverage.merge(coverage);
+            }
+            match result {
+                Ok(_) => {
+                    println!("test {} ... ok", test.name);
+                    passed += 1;
+                }
+                Err(report) => {
+                    println!("test {} ... FAILED", test.name);
+                    let report = Located::wrap(&loaded.lines)(report);
+                    failures.push((program.name().to_string(), test.name, report));
+                }
+            }
+        }
+        coverages.push(program_coverage);
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (file_name, test_name, report) in &failures {
+            println!("\n---- {} {} ----", file_name, test_name);
+            options.print_report(report, file_name);
+        }
+    }
+
+    let status = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        status,
+        passed,
+        failures.len()
+    );
+    if let Some(output) = &options.coverage {
+        coverage::report(output, &coverages)?;
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(CliError::TestsFailed(failures.len()).into())
+    }
+}
+
+// Every test gets a fresh runtime, which first runs the code before the test
+// so it has the boxes made there, and then the test itself on an empty stack.
+// What the code before prints would only get in the way of the report.
+fn run_test(runtime: &mut Runtime, source: &str, test: &TestCase) -> Result<()> {
+    runtime.set_quiet(true);
+    runtime.run()?;
+    runtime.set_quiet(false);
+    runtime.clear_stack();
+    runtime.load(source.to_string(), test.ops.clone());
+    runtime.run()?;
+    Ok(())
+}
+
+// A program, ready to run its tests
+struct Loaded {
+    // as it's written
+    source: String,
+    expanded_source: String,
+    macro_names: Vec<String>,
+    scopes: Scopes,
+    lines: LineMap,
+    expansions: Vec<Expansion>,
+    inclusions: Vec<Inclusion>,
+    // outside of tests
+    ops: VecDeque<Op>,
+    tests: Vec<TestCase>,
+}
+
+fn load(program: &Input, options: &Options) -> Result<Loaded> {
+    let source = program.read()?;
+    let mut preprocessor = options.preprocessor(program, &source);
+    let expanded_source = preprocessor.expand()?;
+
+    let tokens = Lexer::new(expanded_source.as_str()).lex()?;
+    let mut parser = Parser::new(tokens, expanded_source.clone())
+        .with_inclusions(preprocessor.inclusions().to_vec())
+        .with_expansions(preprocessor.expansions().to_vec());
+    let ops = parser.parse()?;
+
+    Ok(Loaded {
+        source,
+        expanded_source,
+        macro_names: preprocessor.macro_names(),
+        scopes: preprocessor.scopes().clone(),
+        lines: preprocessor.lines().clone(),
+        expansions: preprocessor.expansions().to_vec(),
+        inclusions: preprocessor.inclusions().to_vec(),
+        ops,
+        tests: parser.take_tests(),
+    })
+}
+
+// Every `.roth` file under `dir`, skipping hidden directories
+fn discover(dir: &Path) -> Vec<Input> {
+    let mut files = Vec::new();
+    walk(dir, &mut files);
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|path| Input::File(path.to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if path.is_dir() && !hidden {
+            walk(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "roth") {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_test;
+    use crate::{lexer::Lexer, parser::Parser, runtime::Runtime};
+    use expect_test::expect;
+
+    #[test]
+    fn test_isolated_tests() {
+        let source = "box type::int x
+1 x pack 5 \"not shown\" print
+test \"adds\" 1 2 + 3 eq assert end
+test \"fails\" 1 2 + 4 eq assert end
+test \"changes a box\" x unpack 1 assert-eq 2 x pack end
+test \"gets its own boxes\" x unpack 1 assert-eq end
+test \"gets an empty stack\" 0 assert-stack-depth end
+test \"has no later boxes\" y unpack end
+box type::int y";
+        let tokens = Lexer::new(source).lex().unwrap();
+        let mut parser = Parser::new(tokens, source.to_string());
+        let ops = parser.parse().unwrap();
+
+        let actual: Vec<String> = parser
+            .take_tests()
+            .into_iter()
+            .map(|test| {
+                let setup = ops.iter().take(test.setup).cloned().collect();
+                let mut runtime = Runtime::new(source.to_string(), setup);
+                let outcome = match run_test(&mut runtime, source, &test) {
+                    Ok(_) => String::from("ok"),
+                    Err(e) => e.code().unwrap().to_string(),
+                };
+                format!("{}: {}", test.name, outcome)
+            })
+            .chain(std::iter::once(format!(
+                "{} op(s) outside of tests",
+                ops.len()
+            )))
+            .collect();
+
+        let expected = expect![[r#"
+            [
+                "adds: ok",
+                "fails: roth::assertion_failed",
+                "changes a box: ok",
+                "gets its own boxes: ok",
+                "gets an empty stack: ok",
+                "has no later boxes: roth::unknown_box",
+                "8 op(s) outside of tests",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -82,6 +82,18 @@ pub enum TokenKind {
     // 'macro'
     Macro,
 
+    // 'test'
+    Test,
+
+    // 'module'
+    Module,
+
+    // 'export'
+    Export,
+
+    // 'import("file")', which the preprocessor leaves in front of the module
+    Import,
+
     // 'dup'
     Dup,
 
@@ -134,5 +146,23 @@ pub enum TokenKind {
     Unpack,
 
     // 'until'
-    Until
+    Until,
+
+    // 'assert-eq'
+    AssertEq,
+
+    // 'assert-ne'
+    AssertNe,
+
+    // 'assert-type'
+    AssertType,
+
+    // 'assert-stack-depth'
+    AssertStackDepth,
+
+    // 'breakpoint'
+    Breakpoint,
+
+    // '// ...', only kept when the lexer is asked to (see `Lexer::with_comments`)
+    Comment,
 }
@@ -0,0 +1,163 @@
+use serde_json::{json, Value};
+
+use crate::{
+    op::Op,
+    preprocessor::{Expansion, LineMap},
+    stack::Stack,
+    val::{Val, ValKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // `--trace`
+    Text,
+    // `--trace=json`, one object per line
+    Json,
+}
+
+// Describes every op the runtime runs, along with the stack before and after it,
+// so two runs of a program can be diffed
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    format: TraceFormat,
+    // what to call the program itself
+    name: String,
+    lines: LineMap,
+    expansions: Vec<Expansion>,
+}
+
+impl Tracer {
+    pub fn new(
+        format: TraceFormat,
+        name: &str,
+        lines: LineMap,
+        expansions: Vec<Expansion>,
+    ) -> Self {
+        Self {
+            format,
+            name: name.to_string(),
+            lines,
+            expansions,
+        }
+    }
+
+    pub fn line(&self, source: &str, op: &Op, before: &Stack, after: &Stack) -> String {
+        let (file, line) = match self.lines.line_of(op.span.offset()) {
+            Some((file, line)) => (file.unwrap_or(&self.name), Some(line)),
+            None => (self.name.as_str(), None),
+        };
+        // innermost first
+        let macros: Vec<&str> = self
+            .expansions
+            .iter()
+            .rev()
+            .filter(|e| {
+                let start = e.span.offset();
+                start <= op.span.offset() && op.span.offset() < start + e.span.len()
+            })
+            .map(|e| e.name.as_str())
+            .collect();
+        // spans count chars
+        let text: String = source
+            .chars()
+            .skip(op.span.offset())
+            .take(op.span.len())
+            .collect();
+
+        match self.format {
+            TraceFormat::Text => {
+                let location = match line {
+                    Some(line) => format!("{}:{}", file, line),
+                    None => file.to_string(),
+                };
+                let within: String = macros.iter().map(|m| format!(" (in {})", m)).collect();
+                format!(
+                    "{}{}\t{}\t{} -> {}",
+                    location,
+                    within,
+                    text,
+                    describe(before),
+                    describe(after)
+                )
+            }
+            TraceFormat::Json => json!({
+                "file": file,
+                "line": line,
+                "macros": macros,
+                "op": text,
+                "before": before.iter().map(to_json).collect::<Vec<_>>(),
+                "after": after.iter().map(to_json).collect::<Vec<_>>(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+fn describe(stack: &Stack) -> String {
+    let vals: Vec<String> = stack.iter().map(|val| val.literal()).collect();
+    format!("[{}]", vals.join(", "))
+}
+
+fn to_json(val: &Val) -> Value {
+    match val.kind() {
+        ValKind::Int { val } => json!(val),
+        ValKind::Str { val } => json!(val),
+        ValKind::Bool { val } => json!(val),
+        _ => json!(val.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        lexer::Lexer,
+        parser::Parser,
+        preprocessor::PreProcessor,
+        runtime::Runtime,
+        trace::{TraceFormat, Tracer},
+    };
+    use expect_test::expect;
+
+    #[test]
+    fn test_trace() {
+        let mut preprocessor = PreProcessor::new("macro sq dup * end\n1 2 3\nrot sq");
+        let source = preprocessor.expand().unwrap();
+        let tokens = Lexer::new(&source).lex().unwrap();
+        let ops = Parser::new(tokens, source.clone()).parse().unwrap();
+
+        let mut actual = Vec::new();
+        for format in [TraceFormat::Text, TraceFormat::Json] {
+            let tracer = Tracer::new(
+                format,
+                "t.roth",
+                preprocessor.lines().clone(),
+                preprocessor.expansions().to_vec(),
+            );
+            let mut runtime = Runtime::new(source.clone(), ops.clone());
+            while let Some(op) = runtime.next_op().cloned() {
+                let before = runtime.stack().clone();
+                runtime.step().unwrap();
+                actual.push(tracer.line(&source, &op, &before, runtime.stack()));
+            }
+        }
+
+        let expected = expect![[r#"
+            [
+                "t.roth:2\t1\t[] -> [1]",
+                "t.roth:2\t2\t[1] -> [1, 2]",
+                "t.roth:2\t3\t[1, 2] -> [1, 2, 3]",
+                "t.roth:3\trot\t[1, 2, 3] -> [2, 3, 1]",
+                "t.roth:3 (in sq)\tdup\t[2, 3, 1] -> [2, 3, 1, 1]",
+                "t.roth:3 (in sq)\t*\t[2, 3, 1, 1] -> [2, 3, 1]",
+                "{\"after\":[1],\"before\":[],\"file\":\"t.roth\",\"line\":2,\"macros\":[],\"op\":\"1\"}",
+                "{\"after\":[1,2],\"before\":[1],\"file\":\"t.roth\",\"line\":2,\"macros\":[],\"op\":\"2\"}",
+                "{\"after\":[1,2,3],\"before\":[1,2],\"file\":\"t.roth\",\"line\":2,\"macros\":[],\"op\":\"3\"}",
+                "{\"after\":[2,3,1],\"before\":[1,2,3],\"file\":\"t.roth\",\"line\":3,\"macros\":[],\"op\":\"rot\"}",
+                "{\"after\":[2,3,1,1],\"before\":[2,3,1],\"file\":\"t.roth\",\"line\":3,\"macros\":[\"sq\"],\"op\":\"dup\"}",
+                "{\"after\":[2,3,1],\"before\":[2,3,1,1],\"file\":\"t.roth\",\"line\":3,\"macros\":[\"sq\"],\"op\":\"*\"}",
+            ]
+        "#]];
+
+        expected.assert_debug_eq(&actual);
+    }
+}
@@ -21,6 +21,27 @@ impl Val {
     pub fn kind(&self) -> &ValKind {
         &self.kind
     }
+
+    // What `--max-memory` counts this value as: only strings take up room
+    pub fn bytes(&self) -> usize {
+        match &self.kind {
+            ValKind::Str { val } => val.len(),
+            _ => 0,
+        }
+    }
+
+    // `None` for type values (i.e. `type::int`), which don't have a type of their own
+    pub fn val_type(&self) -> Option<ValType> {
+        match &self.kind {
+            ValKind::Int { .. } => Some(ValType::Int),
+            ValKind::Str { .. } => Some(ValType::Str),
+            ValKind::Bool { .. } => Some(ValType::Bool),
+            ValKind::Type { .. } => None,
+            ValKind::BoxedInt { .. } => Some(ValType::BoxedInt),
+            ValKind::BoxedStr { .. } => Some(ValType::BoxedStr),
+            ValKind::BoxedBool { .. } => Some(ValType::BoxedBool),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -33,7 +54,7 @@ pub enum ValType {
     BoxedBool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValKind {
     Int { val: i128 },
     Str { val: String },
@@ -44,6 +65,21 @@ pub enum ValKind {
     BoxedBool { box_id: usize },
 }
 
+impl ValType {
+    // Names used in stack effect signatures, with or without the `type::` prefix
+    pub fn from_name(name: &str) -> Option<ValType> {
+        match name.strip_prefix("type::").unwrap_or(name) {
+            "int" => Some(ValType::Int),
+            "str" => Some(ValType::Str),
+            "bool" => Some(ValType::Bool),
+            "box<int>" => Some(ValType::BoxedInt),
+            "box<str>" => Some(ValType::BoxedStr),
+            "box<bool>" => Some(ValType::BoxedBool),
+            _ => None,
+        }
+    }
+}
+
 impl std::fmt::Display for ValType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -91,9 +127,94 @@ impl Display for Val {
 impl Val {
     fn merge_spans(&self, op: SourceSpan) -> SourceSpan {
         let off = self.span.offset();
-        let len = op.offset() + op.len();
+        let end = op.offset() + op.len();
+
+        (off, end.saturating_sub(off)).into()
+    }
 
-        (off, len).into()
+    // How the value would be written in source, for assertion messages
+    pub fn literal(&self) -> String {
+        match &self.kind {
+            ValKind::Str { val } => format!("{:?}", val),
+            _ => self.to_string(),
+        }
+    }
+
+    // ( actual expected -- )
+    pub fn assert_eq(
+        self,
+        expected: Self,
+        source: &str,
+        op_span: SourceSpan,
+    ) -> Result<(), RuntimeError> {
+        // values of different types are never equal
+        if self.kind == expected.kind {
+            return Ok(());
+        }
+
+        Err(RuntimeError::AssertEqFailed(
+            source.to_string(),
+            expected.literal(),
+            self.literal(),
+            expected.span,
+            self.span,
+            op_span,
+        ))
+    }
+
+    // ( actual unexpected -- )
+    pub fn assert_ne(
+        self,
+        other: Self,
+        source: &str,
+        op_span: SourceSpan,
+    ) -> Result<(), RuntimeError> {
+        if self.kind != other.kind {
+            return Ok(());
+        }
+
+        Err(RuntimeError::AssertNeFailed(
+            source.to_string(),
+            self.literal(),
+            self.span,
+            other.span,
+            op_span,
+        ))
+    }
+
+    // ( val type -- )
+    pub fn assert_type(
+        self,
+        expected: Self,
+        source: &str,
+        op_span: SourceSpan,
+    ) -> Result<(), RuntimeError> {
+        let expected_type = match expected.kind {
+            ValKind::Type { val } => val,
+            _ => {
+                return Err(RuntimeError::InvalidAssertType(
+                    source.to_string(),
+                    expected.span,
+                    op_span,
+                ))
+            }
+        };
+        if self.val_type().as_ref() == Some(&expected_type) {
+            return Ok(());
+        }
+
+        // a type itself doesn't have a type we can name, and the help already says "a"
+        let actual = self
+            .val_type()
+            .map_or(String::from("type"), |t| t.to_string());
+        Err(RuntimeError::AssertTypeFailed(
+            source.to_string(),
+            expected_type.to_string(),
+            actual,
+            expected.span,
+            self.span,
+            op_span,
+        ))
     }
 
     pub fn add(self, other: Self, source: &str, op_span: SourceSpan) -> Result<Self, RuntimeError> {